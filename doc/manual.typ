@@ -163,10 +163,13 @@ At a glance, KLYP's interface mainly consists of:
   column-gutter: 1em,
   row-gutter: 0.75em,
   [*Controls*],              [Sliders that affect KLYP's clipping curve.],
+  [*Multiband Settings*],   [Menu to split the audio into bands that are clipped separately.],
   [*Antialiasing Settings*], [Menu to apply settings that reduce aliasing.],
   [*Visualizers*],           [Cohesive, rich visualizers that are interactive.],
 )
 
+KLYP works on mono, stereo, LCR, quad, 5.1, 7.1 and 7.1.4 tracks.
+
 The following sections go over each of these in detail.
 
 #pagebreak()
@@ -184,6 +187,20 @@ The sliders in this section affect KLYP's clipping curve.
   [*`PRE-GAIN`*],  [Boosts or attenuates the incoming audio.],
   [*`SOFTNESS`*],  [Interpolates the clipping curve between hard and soft.\ The higher the softness, the less harsh the distortion.],
   [*`THRESHOLD`*], [Changes at what level audio starts to clip.],
+  [*`MIX`*],       [Blends the clipped audio with the unprocessed audio for parallel clipping.\ The unprocessed audio is delayed to stay in phase with the clipped audio.],
+  [*`OUTPUT GAIN`*], [Boosts or attenuates the outgoing audio.],
+  [*`SHAPE`*],     [Selects the nonlinearity used in the knee of the clipping curve.\ `Hard` ignores the softness and clips straight at the threshold.],
+  [*`LINK +/-`*],  [Links the negative half of the clipping curve to the positive half.\ Unlink it to set a separate threshold and softness for negative samples, which adds even harmonics.],
+  [*`BIAS`*],      [Shifts the audio along the clipping curve before clipping, relative to the threshold.\ This makes even a symmetric curve saturate asymmetrically, similar to a biased tube stage.],
+  [*`EMPHASIS`*],  [Tilts the audio around the emphasis frequency before clipping, and tilts it back afterwards.\ Positive values make the highs clip first, negative values the lows. The tonal balance doesn't change as long as nothing clips.],
+  [*`DC FILTER`*], [Removes the DC offset that asymmetric clipping produces.\ `Auto` only filters while the clipping is asymmetric or biased, and fades the filter in and out when that changes.],
+  [*`GAIN COMP.`*], [Compensates the clipped audio's level for fair comparisons with the unprocessed audio.\ `Pre-Gain` applies the inverse of the pre-gain. `Loudness` continuously matches the clipped audio's loudness to the incoming audio's, measured like LUFS over the last few seconds.],
+  [*`DELTA`*],     [Outputs the unprocessed audio minus the processed audio, which is mostly what the clipping removes.\ The unprocessed audio is delayed to line up with the processed audio. Anything else that changes the audio shows up in the delta as well, such as `PRE-GAIN` when `GAIN COMP.` doesn't compensate it, `DC FILTER`, automating `EMPHASIS`, the phase shifts of `Linkwitz-Riley` crossovers and the slight high-frequency rolloff of oversampling and antiderivatives. `MIX` and `OUTPUT GAIN` scale the delta too.],
+  [*`STEREO LINK`*], [Clips all channels with the same gain, taken from whichever channel clips hardest.\ This keeps the stereo image from shifting when one side is hotter. In between, linked and independent clipping are blended.],
+  [*`LINK GROUP`*], [Selects which channels `STEREO LINK` links in surround layouts.\ `All` links every channel, while `Front` only links the left, right and center channels. The LFE channel is never linked.],
+  [*`CLIP LFE`*],  [Whether the LFE channel is clipped in 5.1, 7.1 and 7.1.4 layouts.\ When it's off, the LFE channel skips `PRE-GAIN`, `EMPHASIS`, `GAIN COMP.` and the clipping, and is only delayed to stay in sync with the other channels. `MIX`, `OUTPUT GAIN` and `DC FILTER` still apply to it.],
+  [*`STEREO`*],    [Selects which channels are clipped in stereo.\ `L/R` clips the left and right channels separately. `M/S` clips the mid and side channels separately instead. `Mid` and `Side` only clip one of the two and leave the other untouched.],
+  [*`MID/SIDE OFFSETS`*], [Offset the threshold for the mid and side channels in the mid/side modes.\ Lower the mid threshold to catch transients in the center without crushing the stereo image.],
 )
 
 // TODO Image of single slider
@@ -195,6 +212,32 @@ Double-click it to type in its value.\
 
 #pagebreak()
 
+= Multiband Settings
+
+Clipping the full band at once lets loud low frequencies, like a kick drum, pull down everything else along with them.
+KLYP can split the audio into up to four bands instead, and clip each of them on its own with its own oversampling.
+The bands add back up to the unprocessed audio as long as nothing clips.
+
+Select the Multiband dropdown to access these settings.
+Each band is shown with a small view of its clipping curve.
+
+// TODO Image
+
+#grid(
+  columns:2,
+  column-gutter: 1em,
+  row-gutter: 0.75em,
+  [*`BANDS`*],      [Selects how many bands the audio is split into.\ With `1 Band`, the audio is clipped as a whole and the other multiband settings have no effect.],
+  [*`CROSSOVER`*],  [Selects the filters that split the audio.\ `Linkwitz-Riley` filters don't add latency, but shift the phase around the crossover frequencies. `Linear-Phase` filters keep the phase intact, but add about 23 ms of latency and use considerably more CPU, which grows with the number of bands and channels.],
+  [*`PROTECT LOWS`*], [Keeps the frequencies below the slider's frequency out of the clipping entirely.\ This is a cheaper way to keep a bass line clean than splitting the audio into bands, and it works together with them. The lows are delayed to stay in phase with the clipped audio. Switching it on or off briefly crossfades, so it can be toggled during playback without clicks.],
+  [*`CROSSOVERS`*], [Set the frequencies the bands are split at, from low to high.\ Only the first crossover is used with two bands, and the first two with three bands.],
+  [*`BAND OFFSETS`*], [Offset the threshold and the softness for each band, relative to `THRESHOLD` and `SOFTNESS`.\ Lower the lowest band's threshold to tame the low end without dulling the rest.],
+)
+
+Changing the number of bands or the crossover type while audio is playing can cause a click.
+
+#pagebreak()
+
 = Antialiasing Settings
 
 Antialiasing reduces aliases, which are unwanted frequencies that ocurr as a natural consequence of digital distortion.
@@ -202,6 +245,7 @@ Antialiasing reduces aliases, which are unwanted frequencies that ocurr as a nat
 KLYP uses linear-phase oversampling to antialias while eliminating cramping in the upper frequencies.
 High factors of oversampling increases CPU use and leads to increased latency as well as subtle pre-ringing.
 KLYP also provides antiderivative antialiasing for a lower-cost improvement on top of oversampling.
+Changing the oversampling factor or the antiderivative order briefly crossfades between the old and the new settings, so they can be changed during playback without clicks.
 
 Select the Antialiasing dropdown to access these settings.
 
@@ -211,8 +255,13 @@ Select the Antialiasing dropdown to access these settings.
   columns:2,
   column-gutter: 1em,
   row-gutter: 0.75em,
-  [*`OVERSAMPLING`*],   [Controls the factor by which audio is oversampled.\ For instance, when `2x` oversampling is enabled in a host with a 44.1 kHz sample rate, audio is processed at 88.2 kHz.],
-  [*`ANTIDERIVATIVE`*], [Controls whether antiderivative antialiasing is used.],
+  [*`OVERSAMPLING`*],   [Controls the factor by which audio is oversampled.\ For instance, when `2x` oversampling is enabled in a host with a 44.1 kHz sample rate, audio is processed at 88.2 kHz. Up to `32x` oversampling is available.],
+  [*`FILTER QUALITY`*], [Selects the filters used for oversampling. `Draft` uses a short Lanczos kernel that lets some aliasing fold back near the top of the spectrum. `Normal`, `High` and `Ultra` use progressively steeper filters that remove more of it, at the cost of CPU and latency.],
+  [*`FILTER PHASE`*],   [Selects linear-phase or minimum-phase oversampling filters. `Linear` filters keep the phase intact but add latency, especially at higher qualities. `Minimum` filters only add a few samples of latency, which makes them suitable for tracking, but they shift the phase of the high frequencies.],
+  [*`ANTIDERIVATIVE`*], [Selects the order of antiderivative antialiasing. Higher orders suppress more aliasing at the cost of CPU, latency and a little high-frequency rolloff. `Auto` picks the order from the oversampling factor: off at 1x, `1st` at 2x and `2nd` at 4x and above.],
+  [*`RENDER OVERSAMPLING`*], [Replaces the oversampling factor while the host renders offline, for instance when bouncing or exporting. `Same` uses the regular oversampling factor. This lets you track at a low factor and export at a high one. The latency changes along with the factor, which hosts compensate for when they start the render.],
+  [*`RENDER ANTIDERIVATIVE`*], [Replaces the antiderivative order while rendering offline. `Same` uses the regular order.],
+  [*`CONSTANT LATENCY`*], [Always reports the highest latency any oversampling factor, antiderivative order and crossover can have with the selected filters, and delays the output to match. The oversampling factor, antiderivative order, number of bands and crossover type can then be changed or automated without the host having to readjust its latency compensation. Changing the filter quality or phase still changes the latency.],
 )
 
 #pagebreak()
@@ -241,8 +290,9 @@ The filled in portion of it visualizes the input level.
 
 // TODO Image of oscope
 
-The oscilloscope shows the waveform of the output audio.
+The oscilloscope shows the waveform of the output audio, averaged over every channel except the LFE channel.
 Behind it, the input audio waveform is faintly visible.
+When `DELTA` is on, the oscilloscope shows the delta signal instead of the output audio.
 
 == Threshold Line
 
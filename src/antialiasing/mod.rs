@@ -1,64 +1,82 @@
+mod shape;
+mod simd;
+
+pub use shape::{ClipShape, Curve};
+pub use simd::SimdProcessor;
+
 use crate::Antiderivative;
-use std::f64::consts::PI;
 
 /// First-order Antiderivative Antialiasing (ADAA)
 #[derive(Default, Clone)]
 pub struct Processor {
     x1: f64,
     x2: f64,
+    x3: f64,
     ad1_x1: f64,
     ad2_x1: f64,
     d2: f64,
-    s: f64,
+    ad3_x1: f64,
+    ad3_x2: f64,
+    ad3_x3: f64,
+    /// The curve the cached antiderivatives above were evaluated with.
+    curve: Curve,
 }
 
-const TOL: f64 = 1.0e-5;
-const TOL_SOFTNESS: f64 = 1.0e-5;
+pub(super) const TOL: f64 = 1.0e-5;
+/// The third divided difference divides by three differences in a row, so it needs a much larger
+/// tolerance to keep rounding errors in check.
+const TOL_THIRD_DEGREE: f64 = 1.0e-3;
 
 impl Processor {
-    pub fn process(&mut self, x: f64, s: f64, antiderivative: &Antiderivative) -> f64 {
+    /// Forget the previous samples, as if the processor was just created.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn process(&mut self, x: f64, curve: &Curve, antiderivative: &Antiderivative) -> f64 {
         use Antiderivative::*;
+
+        // The divided differences below are only meaningful if every antiderivative in them
+        // belongs to the same curve. When the curve changes, for instance because the softness is
+        // being automated, the previous samples' antiderivatives are evaluated again using the new
+        // curve. That way the antialiasing keeps working during automation instead of falling back
+        // to the plain curve.
+        if *curve != self.curve {
+            self.curve = *curve;
+            self.update_history(antiderivative);
+        }
+
         match antiderivative {
-            Off => self.func(x, s),
+            Off => curve.func(x),
             FirstDegree => {
-                let ad1_x = self.func_ad1(x, s);
+                let ad1_x = curve.func_ad1(x);
 
-                let func = self.func(0.5 * (x + self.x1), s);
-                let derivative_diff = (ad1_x - self.ad1_x1) / (x - self.x1);
-
-                let y = if (s - self.s).abs() > TOL_SOFTNESS || (x - self.x1).abs() < TOL {
-                    func
+                let y = if (x - self.x1).abs() < TOL {
+                    curve.func(0.5 * (x + self.x1))
                 } else {
-                    derivative_diff
+                    (ad1_x - self.ad1_x1) / (x - self.x1)
                 };
 
                 self.ad1_x1 = ad1_x;
                 self.x1 = x;
-                self.s = s;
 
                 y
             }
             SecondDegree => {
-                let ad2_x0 = self.func_ad2(x, s);
+                let ad2_x0 = curve.func_ad2(x);
 
-                let d1 = if (x - self.x1).abs() < TOL {
-                    self.func_ad1(0.5 * (x + self.x1), s)
-                } else {
-                    (ad2_x0 - self.ad2_x1) / (x - self.x1)
-                };
+                let d1 = Self::divided_difference(curve, x, self.x1, ad2_x0, self.ad2_x1);
 
-                let y = if (s - self.s).abs() > TOL_SOFTNESS {
-                    self.func(0.5 * (x + self.x1), s)
-                } else if (x - self.x2).abs() < TOL {
+                let y = if (x - self.x2).abs() < TOL {
                     let x_bar = 0.5 * (x + self.x2);
                     let delta = x_bar - self.x1;
 
                     if delta.abs() < TOL {
-                        self.func(0.5 * (x_bar + self.x1), s)
+                        curve.func(0.5 * (x_bar + self.x1))
                     } else {
                         2.0 / delta
-                            * (self.func_ad1(x_bar, s)
-                                + (self.ad2_x1 - self.func_ad2(x_bar, s)) / delta)
+                            * (curve.func_ad1(x_bar)
+                                + (self.ad2_x1 - curve.func_ad2(x_bar)) / delta)
                     }
                 } else {
                     2.0 / (x - self.x2) * (d1 - self.d2)
@@ -69,103 +87,264 @@ impl Processor {
                 self.x1 = x;
                 self.ad2_x1 = ad2_x0;
 
+                y
+            }
+            ThirdDegree => {
+                let ad3_x0 = curve.func_ad3(x);
+
+                let y = 6.0
+                    * Self::third_divided_difference(
+                        curve,
+                        [
+                            (x, ad3_x0),
+                            (self.x1, self.ad3_x1),
+                            (self.x2, self.ad3_x2),
+                            (self.x3, self.ad3_x3),
+                        ],
+                    );
+
+                self.x3 = self.x2;
+                self.x2 = self.x1;
+                self.x1 = x;
+                self.ad3_x3 = self.ad3_x2;
+                self.ad3_x2 = self.ad3_x1;
+                self.ad3_x1 = ad3_x0;
+
                 y
             }
         }
     }
+
+    /// Evaluate the antiderivatives of the previous samples again using `self.curve`.
+    fn update_history(&mut self, antiderivative: &Antiderivative) {
+        use Antiderivative::*;
+        match antiderivative {
+            Off => (),
+            FirstDegree => {
+                self.ad1_x1 = self.curve.func_ad1(self.x1);
+            }
+            SecondDegree => {
+                let ad2_x2 = self.curve.func_ad2(self.x2);
+
+                self.ad2_x1 = self.curve.func_ad2(self.x1);
+                self.d2 =
+                    Self::divided_difference(&self.curve, self.x1, self.x2, self.ad2_x1, ad2_x2);
+            }
+            ThirdDegree => {
+                self.ad3_x1 = self.curve.func_ad3(self.x1);
+                self.ad3_x2 = self.curve.func_ad3(self.x2);
+                self.ad3_x3 = self.curve.func_ad3(self.x3);
+            }
+        }
+    }
+
+    /// The divided difference of the second antiderivative between `x0` and `x1`, where `ad2_x0`
+    /// and `ad2_x1` are the second antiderivative evaluated at those points. Falls back to the
+    /// first antiderivative at the midpoint if the two points are too close together.
     #[inline]
-    fn func(&mut self, x: f64, s: f64) -> f64 {
-        static KNEE_OFFSET: f64 = PI / 2.0 - 1.0;
-        let lower_bound = 1.0 - s;
-        let upper_bound = 1.0 + s * KNEE_OFFSET;
-
-        if x.abs() < lower_bound {
-            x
-        } else if x.abs() < upper_bound {
-            (1.0 - s + s * (1.0 - (1.0 - x.abs()) / s).sin()) * x.signum()
+    fn divided_difference(curve: &Curve, x0: f64, x1: f64, ad2_x0: f64, ad2_x1: f64) -> f64 {
+        if (x0 - x1).abs() < TOL {
+            curve.func_ad1(0.5 * (x0 + x1))
         } else {
-            x.signum()
+            (ad2_x0 - ad2_x1) / (x0 - x1)
         }
     }
+    /// The third divided difference of the third antiderivative through four `(x, ad3(x))` points.
+    /// Divided differences don't depend on the order of their points, so the points are sorted
+    /// first to keep the differences that are divided by as large as possible. Any span that is
+    /// still too small to divide by is replaced by a Taylor expansion around its points' mean,
+    /// written in terms of the curve and its lower antiderivatives. Each expansion is accurate
+    /// enough that its error is still of the second order in the span once it has been divided by
+    /// the larger spans above it, so the levels don't undo each other's accuracy. Where the curve
+    /// is linear, all of them are exact.
     #[inline]
-    fn func_ad1(&mut self, x: f64, s: f64) -> f64 {
-        static KNEE_OFFSET: f64 = PI / 2.0 - 1.0;
-        let lower_bound = 1.0 - s;
-        let upper_bound = 1.0 + s * KNEE_OFFSET;
+    fn third_divided_difference(curve: &Curve, mut points: [(f64, f64); 4]) -> f64 {
+        points.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+        let [(a, ad3_a), (b, ad3_b), (c, ad3_c), (d, ad3_d)] = points;
+
+        // F3[x0, x1] = F2(m) + f(m) (x1 - x0)² / 24 + O(h⁴)
+        let first = |x0: f64, ad3_x0: f64, x1: f64, ad3_x1: f64| {
+            let span = x1 - x0;
+            if span < TOL_THIRD_DEGREE {
+                let mean = 0.5 * (x0 + x1);
+                curve.func_ad2(mean) + curve.func(mean) * span * span / 24.0
+            } else {
+                (ad3_x1 - ad3_x0) / span
+            }
+        };
+        // F3[x0, x1, x2] = F1(m) / 2 + f'(m) Σ(xi - m)² / 48 + O(h³), where the slope is estimated
+        // from the outer points
+        let second = |x0: f64, x1: f64, x2: f64, d0: f64, d1: f64| {
+            let span = x2 - x0;
+            if span < TOL_THIRD_DEGREE {
+                let mean = (x0 + x1 + x2) / 3.0;
+                let spread = (x0 - mean).powi(2) + (x1 - mean).powi(2) + (x2 - mean).powi(2);
+                let correction = if spread > 0.0 {
+                    (curve.func(x2) - curve.func(x0)) / span * spread / 48.0
+                } else {
+                    0.0
+                };
 
-        let abs_x = x.abs();
+                0.5 * curve.func_ad1(mean) + correction
+            } else {
+                (d1 - d0) / span
+            }
+        };
 
-        if abs_x < lower_bound {
-            x.powi(2) / 2.0
-        } else if abs_x < upper_bound {
-            let offset_sin = (1.0 - s).powi(2) / 2.0 - 1.0;
+        let ab = first(a, ad3_a, b, ad3_b);
+        let bc = first(b, ad3_b, c, ad3_c);
+        let cd = first(c, ad3_c, d, ad3_d);
 
-            -s.powi(2) * ((1.0 - abs_x) / s - 1.0).cos() + s * (2.0 - abs_x) + abs_x + offset_sin
+        let abc = second(a, b, c, ab, bc);
+        let bcd = second(b, c, d, bc, cd);
+
+        // F3[a, b, c, d] = f(m) / 6 + O(h²)
+        if d - a < TOL_THIRD_DEGREE {
+            curve.func((a + b + c + d) / 4.0) / 6.0
         } else {
-            let offset_clip = -s.powi(2) * (-PI / 2.0).cos()
-                - s * ((s + 1.0) * (PI / 2.0 - 1.0) - PI / 2.0)
-                + (1.0 - s).powi(2) / 2.0
-                - 1.0;
+            (bcd - abc) / (d - a)
+        }
+    }
+}
 
-            abs_x + offset_clip
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MAX_OVERSAMPLING_FACTOR;
+    use std::f64::consts::PI;
+
+    /// A sine at `frequency` Hz with the given amplitude, sampled at 48 kHz times the oversampling
+    /// factor.
+    fn sine(frequency: f64, amplitude: f64, oversampling: usize, seconds: f64) -> Vec<f64> {
+        let sample_rate = 48000.0 * (1 << oversampling) as f64;
+        (0..(seconds * sample_rate) as usize)
+            .map(|i| amplitude * (2.0 * PI * frequency * i as f64 / sample_rate).sin())
+            .collect()
+    }
+
+    /// The share of `output`'s energy that isn't within a few bins of `fundamental_bin`'s
+    /// harmonics, in dB. The output is windowed, since ramping the curve spreads the harmonics a
+    /// little on its own.
+    fn alias_db(output: &[f64], fundamental_bin: usize) -> f64 {
+        let n = output.len();
+        let (cos, sin): (Vec<f64>, Vec<f64>) = (0..n)
+            .map(|i| (2.0 * PI * i as f64 / n as f64).sin_cos())
+            .map(|(sin, cos)| (cos, sin))
+            .unzip();
+        // A Blackman-Harris window
+        let windowed: Vec<f64> = output
+            .iter()
+            .enumerate()
+            .map(|(i, x)| {
+                x * (0.35875 - 0.48829 * cos[i] + 0.14128 * cos[2 * i % n]
+                    - 0.01168 * cos[3 * i % n])
+            })
+            .collect();
+
+        let (mut total, mut alias) = (0.0, 0.0);
+        for bin in 0..n / 2 {
+            let (re, im) = windowed
+                .iter()
+                .enumerate()
+                .fold((0.0, 0.0), |(re, im), (i, x)| {
+                    (re + x * cos[bin * i % n], im - x * sin[bin * i % n])
+                });
+            let energy = re * re + im * im;
+
+            let harmonic = bin % fundamental_bin;
+            let near_harmonic = harmonic.min(fundamental_bin - harmonic) <= 40 || bin < 40;
+            total += energy;
+            if !near_harmonic {
+                alias += energy;
+            }
         }
+
+        10.0 * (alias / total).log10()
     }
-    #[inline]
-    fn func_ad2(&mut self, x: f64, s: f64) -> f64 {
-        static KNEE_OFFSET: f64 = PI / 2.0 - 1.0;
-        let lower_bound = 1.0 - s;
-        let upper_bound = 1.0 + s * KNEE_OFFSET;
-
-        let abs_x = x.abs();
-
-        if abs_x < lower_bound {
-            x.powi(3) / 6.0
-        } else if abs_x < upper_bound {
-            let offset_sin = if s == 0.0 {
-                1.0 / 6.0
-            } else {
-                s.powi(2) * (1.0 - s) + s.powi(3) * (1.0 - 1.0 / s) - (1.0 - s).powi(3) / 6.0
-            };
-
-            x.signum()
-                * (1.0 / 2.0
-                    * (2.0 * s.powi(3) * ((1.0 - abs_x) / s - 1.0).sin()
-                        + (s.powi(2) + 2.0 * s - 1.0) * abs_x
-                        + (1.0 - s) * x.powi(2)
-                        + 2.0 * (1.0 - 1.0 / s) * s.powi(3))
-                    - offset_sin)
-        } else {
-            let offset_clip = if s == 0.0 {
-                -1.0 / 6.0
-            } else {
-                1.0 / 6.0
-                    * (6.0 * s.powi(3) * (1.0 + (upper_bound - 1.0) / s).sin() - 5.0 * s.powi(3)
-                        + s.powi(2) * (-3.0 * PI * upper_bound + 6.0 * upper_bound + 3.0)
-                        + 3.0 * s * (upper_bound - 1.0).powi(2)
-                        - 1.0)
-            };
-
-            1.0 / 2.0 * x * (s.powi(2) * (3.0 - PI) + abs_x - 1.0) - x.signum() * offset_clip
+
+    const RAMP_LENGTH: usize = 4096;
+    const RAMP_BIN: usize = 467;
+
+    /// Clip a loud sine while the softness ramps up and down between 0.2 and 0.9 every 256
+    /// samples. That's faster than the parameter smoothing ever ramps it, so the history's
+    /// antiderivatives are far off from the new curve's unless they're updated.
+    fn ramp_softness(antiderivative: Antiderivative, update_history: bool) -> Vec<f64> {
+        const PERIOD: usize = 256;
+        let mut processor = Processor::default();
+
+        (0..RAMP_LENGTH)
+            .map(|i| {
+                let x = 3.0 * (2.0 * PI * (RAMP_BIN * i) as f64 / RAMP_LENGTH as f64).sin();
+                let phase = (i % PERIOD) as f64 / PERIOD as f64;
+                let softness = 0.2 + 0.7 * (1.0 - (2.0 * phase - 1.0).abs());
+                let curve = Curve {
+                    shape: ClipShape::SineKnee,
+                    softness,
+                    negative_softness: softness,
+                    negative_threshold: 1.0,
+                };
+                // Pretending the curve hasn't changed skips updating the history
+                if !update_history {
+                    processor.curve = curve;
+                }
+
+                processor.process(x, &curve, &antiderivative)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn curve_automation_keeps_antialiasing() {
+        let plain = alias_db(&ramp_softness(Antiderivative::Off, true), RAMP_BIN);
+
+        for (antiderivative, min_improvement) in [
+            (Antiderivative::FirstDegree, 0.0),
+            (Antiderivative::SecondDegree, 3.0),
+            (Antiderivative::ThirdDegree, 20.0),
+        ] {
+            let updated = alias_db(&ramp_softness(antiderivative, true), RAMP_BIN);
+            let stale = alias_db(&ramp_softness(antiderivative, false), RAMP_BIN);
+            assert!(
+                updated < plain - 5.0 && updated <= stale - min_improvement,
+                "{:?}: {:.1} dB with the history updated, {:.1} dB without, {:.1} dB without ADAA",
+                antiderivative,
+                updated,
+                stale,
+                plain
+            );
+        }
+    }
+
+    #[test]
+    fn third_degree_is_linear_below_threshold() {
+        let curve = Curve {
+            shape: ClipShape::SineKnee,
+            softness: 0.3,
+            negative_softness: 0.3,
+            negative_threshold: 1.0,
+        };
+
+        for oversampling in 0..=MAX_OVERSAMPLING_FACTOR {
+            for frequency in [20.0, 100.0, 1000.0, 5000.0] {
+                let input = sine(frequency, 0.6, oversampling, 0.1);
+                let mut processor = Processor::default();
+
+                // For a linear curve, third-degree ADAA is a moving average over four samples
+                for (i, x) in input.iter().enumerate() {
+                    let y = processor.process(*x, &curve, &Antiderivative::ThirdDegree);
+                    if i >= 3 {
+                        let expected = input[i - 3..=i].iter().sum::<f64>() / 4.0;
+                        assert!(
+                            (y - expected).abs() < 1e-5,
+                            "{}x, {} Hz: {} instead of {}",
+                            1 << oversampling,
+                            frequency,
+                            y,
+                            expected
+                        );
+                    }
+                }
+            }
         }
     }
-    // fn func(&mut self, mut x: f64, s: f64) -> f64 {
-    //     static KNEE_OFFSET: f64 = PI / 2.0 - 1.0;
-    //
-    //     if x.abs() < 1.0 {
-    //         x
-    //     } else {
-    //         x.signum()
-    //     }
-    // }
-    // fn func_ad1(&mut self, mut x: f64, s: f64) -> f64 {
-    //     static KNEE_OFFSET: f64 = PI / 2.0 - 1.0;
-    //
-    //     let abs_x = x.abs();
-    //
-    //     if abs_x < 1.0 {
-    //         x.powi(2) / 2.0
-    //     } else {
-    //         abs_x - 0.5
-    //     }
-    // }
 }
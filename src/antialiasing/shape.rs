@@ -0,0 +1,465 @@
+use nih_plug::prelude::Enum;
+use std::f64::consts::{FRAC_PI_2, LN_2, PI};
+
+/// The nonlinearity used for the clipping curve's knee.
+///
+/// Every shape is linear up to `1 - softness`, then bends into a knee that saturates at 1. Each
+/// knee is defined in terms of a normalized function `g(u)` with `g(0) = 0`, `g'(0) = 1` and
+/// `g(u) -> 1`, scaled by the softness. This lets the curve and its antiderivatives be built the
+/// same way for every shape, with only `g` and its antiderivatives differing.
+#[derive(Enum, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClipShape {
+    #[id = "hard"]
+    #[name = "Hard"]
+    Hard,
+    #[id = "sine"]
+    #[name = "Sine"]
+    #[default]
+    SineKnee,
+    #[id = "tanh"]
+    #[name = "Tanh"]
+    Tanh,
+    #[id = "arctan"]
+    #[name = "Arctan"]
+    Arctan,
+    #[id = "cubic"]
+    #[name = "Cubic"]
+    Cubic,
+    #[id = "sigmoid"]
+    #[name = "Sigmoid"]
+    Exponential,
+}
+
+/// A possibly asymmetric clipping curve at a single point in time. The positive half saturates at
+/// 1, and the negative half at `-negative_threshold`. Both halves use the same shape, but each has
+/// its own softness.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Curve {
+    pub shape: ClipShape,
+    /// The positive half's softness.
+    pub softness: f64,
+    /// The negative half's softness.
+    pub negative_softness: f64,
+    /// The negative half's threshold, relative to the positive half's threshold.
+    pub negative_threshold: f64,
+}
+
+/// Scales the arctangent so that it saturates at 1 while keeping a slope of 1 at the origin.
+const ARCTAN_SCALE: f64 = FRAC_PI_2;
+
+/// The point at which the cubic knee reaches full saturation.
+const CUBIC_LIMIT: f64 = 1.5;
+
+/// `B(2k) / (2k + 1)!` for `k = 1..=10`, used for the dilogarithm's Bernoulli series.
+const DILOG_COEFFICIENTS: [f64; 10] = [
+    0.027777777777777776,
+    -0.0002777777777777778,
+    4.72411186696901e-06,
+    -9.185773074661964e-08,
+    1.8978869988971e-09,
+    -4.0647616451442256e-11,
+    8.921691020456452e-13,
+    -1.9939295860721074e-14,
+    4.518980029619918e-16,
+    -1.0356517612181247e-17,
+];
+
+/// The coefficients of a polynomial approximating the trilogarithm `-Li3(-w)` for `w` in `[0, 1]`,
+/// starting at `w¹`. These are the power series' coefficients with the Cohen-Villegas-Zagier
+/// acceleration for alternating series baked in, which is needed because the regular power series
+/// barely converges near `w = 1`.
+const TRILOG_COEFFICIENTS: [f64; 24] = [
+    1.0,
+    -0.12499999999999989,
+    0.03703703703703008,
+    -0.015624999999774248,
+    0.007999999995264054,
+    -0.0046296295603405055,
+    0.0029154511516724965,
+    -0.0019531189385834505,
+    0.0013717035356687808,
+    -0.000999804429728437,
+    0.0007505123506348205,
+    -0.0005760047558287472,
+    0.00044764713809522885,
+    -0.0003469254699188619,
+    0.00026195346960098943,
+    -0.00018689320396512389,
+    0.00012165436217209708,
+    -6.965498145972945e-05,
+    3.379304442072279e-05,
+    -1.3340586435951931e-05,
+    4.079512551514278e-06,
+    -9.010206273939211e-07,
+    1.273405554545184e-07,
+    -8.621308194248129e-09,
+];
+
+/// Apéry's constant, `ζ(3)`.
+const APERY: f64 = 1.2020569031595942;
+
+impl ClipShape {
+    /// The softness that is actually applied for this shape. The hard clipper has no knee.
+    #[inline]
+    pub fn softness(&self, s: f64) -> f64 {
+        match self {
+            Self::Hard => 0.0,
+            _ => s,
+        }
+    }
+
+    /// The clipping curve itself, saturating at ±1.
+    #[inline]
+    pub fn func(&self, x: f64, s: f64) -> f64 {
+        let s = self.softness(s);
+        let lower_bound = 1.0 - s;
+        let abs_x = x.abs();
+
+        if abs_x < lower_bound {
+            x
+        } else if s == 0.0 {
+            x.signum()
+        } else {
+            (lower_bound + s * self.knee((abs_x - lower_bound) / s)) * x.signum()
+        }
+    }
+
+    /// The clipping curve as a gain computer: the gain [`func()`][Self::func()] applies to a
+    /// sample at `level`, so that `func(x, s) = gain(|x|, s) * x`. Unlike the waveshaper, this gain
+    /// can be computed from one signal's level and applied to another.
+    #[inline]
+    pub fn gain(&self, level: f64, s: f64) -> f64 {
+        let lower_bound = 1.0 - self.softness(s);
+
+        if level <= lower_bound {
+            1.0
+        } else {
+            self.func(level, s) / level
+        }
+    }
+
+    /// First antiderivative of [`func()`][Self::func()], zero at the origin.
+    #[inline]
+    pub fn func_ad1(&self, x: f64, s: f64) -> f64 {
+        let s = self.softness(s);
+        let lower_bound = 1.0 - s;
+        let abs_x = x.abs();
+
+        if abs_x < lower_bound {
+            x.powi(2) / 2.0
+        } else {
+            let d = abs_x - lower_bound;
+            let knee = if s == 0.0 {
+                0.0
+            } else {
+                s.powi(2) * self.knee_ad1(d / s)
+            };
+
+            lower_bound.powi(2) / 2.0 + lower_bound * d + knee
+        }
+    }
+
+    /// Second antiderivative of [`func()`][Self::func()], zero at the origin.
+    #[inline]
+    pub fn func_ad2(&self, x: f64, s: f64) -> f64 {
+        let s = self.softness(s);
+        let lower_bound = 1.0 - s;
+        let abs_x = x.abs();
+
+        if abs_x < lower_bound {
+            x.powi(3) / 6.0
+        } else {
+            let d = abs_x - lower_bound;
+            let knee = if s == 0.0 {
+                0.0
+            } else {
+                s.powi(3) * self.knee_ad2(d / s)
+            };
+
+            x.signum()
+                * (lower_bound.powi(3) / 6.0
+                    + lower_bound.powi(2) / 2.0 * d
+                    + lower_bound * d.powi(2) / 2.0
+                    + knee)
+        }
+    }
+
+    /// Third antiderivative of [`func()`][Self::func()], zero at the origin.
+    #[inline]
+    pub fn func_ad3(&self, x: f64, s: f64) -> f64 {
+        let s = self.softness(s);
+        let lower_bound = 1.0 - s;
+        let abs_x = x.abs();
+
+        if abs_x < lower_bound {
+            x.powi(4) / 24.0
+        } else {
+            let d = abs_x - lower_bound;
+            let knee = if s == 0.0 {
+                0.0
+            } else {
+                s.powi(4) * self.knee_ad3(d / s)
+            };
+
+            lower_bound.powi(4) / 24.0
+                + lower_bound.powi(3) / 6.0 * d
+                + lower_bound.powi(2) / 4.0 * d.powi(2)
+                + lower_bound / 6.0 * d.powi(3)
+                + knee
+        }
+    }
+
+    /// The normalized knee `g(u)` for `u >= 0`.
+    #[inline]
+    fn knee(&self, u: f64) -> f64 {
+        match self {
+            Self::Hard => 1.0,
+            Self::SineKnee => {
+                if u < FRAC_PI_2 {
+                    u.sin()
+                } else {
+                    1.0
+                }
+            }
+            Self::Tanh => u.tanh(),
+            Self::Arctan => (ARCTAN_SCALE * u).atan() / ARCTAN_SCALE,
+            Self::Cubic => {
+                if u < CUBIC_LIMIT {
+                    u - 4.0 / 27.0 * u.powi(3)
+                } else {
+                    1.0
+                }
+            }
+            Self::Exponential => -(-u).exp_m1(),
+        }
+    }
+
+    /// The knee's first antiderivative, zero at `u = 0`.
+    #[inline]
+    fn knee_ad1(&self, u: f64) -> f64 {
+        match self {
+            Self::Hard => u,
+            Self::SineKnee => {
+                if u < FRAC_PI_2 {
+                    1.0 - u.cos()
+                } else {
+                    1.0 + (u - FRAC_PI_2)
+                }
+            }
+            // `ln(cosh(u))`, written so it doesn't overflow for large `u`
+            Self::Tanh => u + (-2.0 * u).exp().ln_1p() - LN_2,
+            Self::Arctan => {
+                let v = ARCTAN_SCALE * u;
+
+                (v * v.atan() - 0.5 * v.powi(2).ln_1p()) / ARCTAN_SCALE.powi(2)
+            }
+            Self::Cubic => {
+                if u < CUBIC_LIMIT {
+                    u.powi(2) / 2.0 - u.powi(4) / 27.0
+                } else {
+                    15.0 / 16.0 + (u - CUBIC_LIMIT)
+                }
+            }
+            Self::Exponential => u + (-u).exp_m1(),
+        }
+    }
+
+    /// The knee's second antiderivative, zero at `u = 0`.
+    #[inline]
+    fn knee_ad2(&self, u: f64) -> f64 {
+        match self {
+            Self::Hard => u.powi(2) / 2.0,
+            Self::SineKnee => {
+                if u < FRAC_PI_2 {
+                    u - u.sin()
+                } else {
+                    let d = u - FRAC_PI_2;
+
+                    (FRAC_PI_2 - 1.0) + d + d.powi(2) / 2.0
+                }
+            }
+            // There is no elementary antiderivative of `ln(cosh(u))`, so this goes through the
+            // dilogarithm instead
+            Self::Tanh => {
+                u.powi(2) / 2.0 - u * LN_2 + 0.5 * dilog((-2.0 * u).exp()) + PI.powi(2) / 24.0
+            }
+            Self::Arctan => {
+                let v = ARCTAN_SCALE * u;
+
+                (0.5 * (v.powi(2) - 1.0) * v.atan() + 0.5 * v - 0.5 * v * v.powi(2).ln_1p())
+                    / ARCTAN_SCALE.powi(3)
+            }
+            Self::Cubic => {
+                if u < CUBIC_LIMIT {
+                    u.powi(3) / 6.0 - u.powi(5) / 135.0
+                } else {
+                    let d = u - CUBIC_LIMIT;
+
+                    81.0 / 160.0 + 15.0 / 16.0 * d + d.powi(2) / 2.0
+                }
+            }
+            Self::Exponential => u.powi(2) / 2.0 - u - (-u).exp_m1(),
+        }
+    }
+
+    /// The knee's third antiderivative, zero at `u = 0`.
+    #[inline]
+    fn knee_ad3(&self, u: f64) -> f64 {
+        match self {
+            Self::Hard => u.powi(3) / 6.0,
+            Self::SineKnee => {
+                if u < FRAC_PI_2 {
+                    u.powi(2) / 2.0 + u.cos() - 1.0
+                } else {
+                    let d = u - FRAC_PI_2;
+
+                    (PI.powi(2) / 8.0 - 1.0)
+                        + (FRAC_PI_2 - 1.0) * d
+                        + d.powi(2) / 2.0
+                        + d.powi(3) / 6.0
+                }
+            }
+            // Just like the second antiderivative, this needs the polylogarithm
+            Self::Tanh => {
+                u.powi(3) / 6.0 - u.powi(2) * LN_2 / 2.0
+                    + PI.powi(2) / 24.0 * u
+                    + trilog((-2.0 * u).exp()) / 4.0
+                    - 3.0 * APERY / 16.0
+            }
+            Self::Arctan => {
+                let v = ARCTAN_SCALE * u;
+
+                ((v.powi(3) / 6.0 - v / 2.0) * v.atan()
+                    + 5.0 / 12.0 * v.powi(2)
+                    + (1.0 / 12.0 - v.powi(2) / 4.0) * v.powi(2).ln_1p())
+                    / ARCTAN_SCALE.powi(4)
+            }
+            Self::Cubic => {
+                if u < CUBIC_LIMIT {
+                    u.powi(4) / 24.0 - u.powi(6) / 810.0
+                } else {
+                    let d = u - CUBIC_LIMIT;
+
+                    63.0 / 320.0 + 81.0 / 160.0 * d + 15.0 / 32.0 * d.powi(2) + d.powi(3) / 6.0
+                }
+            }
+            Self::Exponential => u.powi(3) / 6.0 - u.powi(2) / 2.0 + u + (-u).exp_m1(),
+        }
+    }
+}
+
+impl Default for Curve {
+    fn default() -> Self {
+        Self {
+            shape: ClipShape::default(),
+            softness: 0.0,
+            negative_softness: 0.0,
+            negative_threshold: 1.0,
+        }
+    }
+}
+
+impl Curve {
+    /// Whether both halves of the curve are the same.
+    pub fn is_symmetric(&self) -> bool {
+        self.softness == self.negative_softness && self.negative_threshold == 1.0
+    }
+
+    // The negative half is the positive half's curve scaled by the negative threshold `r` on both
+    // axes, so its antiderivatives are scaled by `r²`, `r³` and `r⁴` respectively. Since every
+    // antiderivative is zero at the origin, the two halves join up without any offsets.
+
+    #[inline]
+    pub fn func(&self, x: f64) -> f64 {
+        if x >= 0.0 {
+            self.shape.func(x, self.softness)
+        } else {
+            let r = self.negative_threshold;
+
+            r * self.shape.func(x / r, self.negative_softness)
+        }
+    }
+
+    /// How far `x` reaches into its half of the curve, relative to that half's threshold.
+    #[inline]
+    pub fn level(&self, x: f64) -> f64 {
+        if x >= 0.0 {
+            x
+        } else {
+            -x / self.negative_threshold
+        }
+    }
+
+    /// The gain the half of the curve with `x`'s polarity applies at the given
+    /// [level][Self::level()], so that `func(x) = gain(x, level(x)) * x`. Passing the largest
+    /// level among several signals instead links their clipping.
+    #[inline]
+    pub fn gain(&self, x: f64, level: f64) -> f64 {
+        if x >= 0.0 {
+            self.shape.gain(level, self.softness)
+        } else {
+            self.shape.gain(level, self.negative_softness)
+        }
+    }
+
+    #[inline]
+    pub fn func_ad1(&self, x: f64) -> f64 {
+        if x >= 0.0 {
+            self.shape.func_ad1(x, self.softness)
+        } else {
+            let r = self.negative_threshold;
+
+            r.powi(2) * self.shape.func_ad1(x / r, self.negative_softness)
+        }
+    }
+
+    #[inline]
+    pub fn func_ad2(&self, x: f64) -> f64 {
+        if x >= 0.0 {
+            self.shape.func_ad2(x, self.softness)
+        } else {
+            let r = self.negative_threshold;
+
+            r.powi(3) * self.shape.func_ad2(x / r, self.negative_softness)
+        }
+    }
+
+    #[inline]
+    pub fn func_ad3(&self, x: f64) -> f64 {
+        if x >= 0.0 {
+            self.shape.func_ad3(x, self.softness)
+        } else {
+            let r = self.negative_threshold;
+
+            r.powi(4) * self.shape.func_ad3(x / r, self.negative_softness)
+        }
+    }
+}
+
+/// The dilogarithm `Li2(-w)` for `w` in `[0, 1]`, computed through its Bernoulli series in
+/// `-ln(1 + w)`. This converges quickly over the entire range, which the regular power series does
+/// not do near `w = 1`.
+#[inline]
+fn dilog(w: f64) -> f64 {
+    let u = -w.ln_1p();
+    let u2 = u.powi(2);
+
+    let mut total = u - u2 / 4.0;
+    let mut power = u;
+    for coefficient in DILOG_COEFFICIENTS {
+        power *= u2;
+        total += coefficient * power;
+    }
+
+    total
+}
+
+/// The trilogarithm `-Li3(-w)` for `w` in `[0, 1]`.
+#[inline]
+fn trilog(w: f64) -> f64 {
+    TRILOG_COEFFICIENTS
+        .iter()
+        .rev()
+        .fold(0.0, |total, coefficient| total * w + coefficient)
+        * w
+}
@@ -0,0 +1,475 @@
+use std::f64::consts::FRAC_PI_2;
+
+use nih_plug::debug::*;
+
+use super::{ClipShape, Curve, Processor, TOL};
+use crate::Antiderivative;
+
+/// `1 / n!` for `n = 0..=21`, used for the knee's Taylor series.
+const INVERSE_FACTORIALS: [f64; 22] = [
+    1.0,
+    1.0,
+    0.5,
+    0.16666666666666666,
+    0.041666666666666664,
+    0.008333333333333333,
+    0.001388888888888889,
+    0.0001984126984126984,
+    2.48015873015873e-05,
+    2.7557319223985893e-06,
+    2.755731922398589e-07,
+    2.505210838544172e-08,
+    2.08767569878681e-09,
+    1.6059043836821613e-10,
+    1.1470745597729725e-11,
+    7.647163731819816e-13,
+    4.779477332387385e-14,
+    2.8114572543455206e-15,
+    1.5619206968586225e-16,
+    8.22063524662433e-18,
+    4.110317623312165e-19,
+    1.9572941063391263e-20,
+];
+
+/// Antiderivative antialiasing for up to `LANES` channels at once.
+///
+/// This does the same thing as the scalar [`Processor`][super::Processor], but for every channel at
+/// the same time, and with the knee evaluated without any branches and without calling into libm.
+/// That lets the compiler turn the loops over the lanes into SIMD instructions. Only the divided
+/// differences' fallbacks for samples that lie too close together branch, and only if one of the
+/// lanes needs them. The scalar processor remains the reference implementation, and it is still
+/// used for everything this processor doesn't [support][Self::supports()].
+///
+/// The samples are passed in as `f32`, but the lanes are processed as `f64`. Antiderivatives
+/// cancel out catastrophically in the divided differences, and in `f32` that leaves too little
+/// precision for second-degree ADAA. Only the lanes that hold a channel are processed, so mono
+/// and stereo audio don't pay for the lanes of a 7.1.4 layout.
+#[derive(Clone)]
+pub struct SimdProcessor<const LANES: usize> {
+    /// The number of lanes in use, counting from the first one.
+    lanes: usize,
+    x1: [f64; LANES],
+    x2: [f64; LANES],
+    ad1_x1: [f64; LANES],
+    ad2_x1: [f64; LANES],
+    d2: [f64; LANES],
+    /// The curve the cached antiderivatives above were evaluated with.
+    curve: Curve,
+    halves: Halves,
+}
+
+/// The positive and negative halves of a [`Curve`], with the softness already adjusted for the
+/// shape.
+#[derive(Debug, Clone, Copy)]
+struct Halves {
+    positive: Half,
+    negative: Half,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Half {
+    softness: f64,
+    lower_bound: f64,
+    /// The half's threshold, which is used to scale it on both axes.
+    scale: f64,
+}
+
+/// A single lane's sample, prepared for evaluating the curve and its antiderivatives. The curve is
+/// split into a linear part of length `linear` and a knee of length `knee`. The knee is split up
+/// further into its curved part, which is normalized to `u` in `[0, pi/2]`, and the straight line
+/// past that, `excess`. Depending on the sample, some of these lengths are zero, which is what
+/// makes the evaluation branchless.
+struct Point {
+    sign: f64,
+    half: Half,
+    linear: f64,
+    knee: f64,
+    u: f64,
+    excess: f64,
+}
+
+impl<const LANES: usize> Default for SimdProcessor<LANES> {
+    fn default() -> Self {
+        Self::new(LANES)
+    }
+}
+
+impl<const LANES: usize> SimdProcessor<LANES> {
+    /// Create a processor that only uses the first `lanes` lanes.
+    pub fn new(lanes: usize) -> Self {
+        nih_debug_assert!(lanes <= LANES);
+        let curve = Curve::default();
+
+        Self {
+            lanes: lanes.min(LANES),
+            x1: [0.0; LANES],
+            x2: [0.0; LANES],
+            ad1_x1: [0.0; LANES],
+            ad2_x1: [0.0; LANES],
+            d2: [0.0; LANES],
+            curve,
+            halves: Halves::new(&curve),
+        }
+    }
+
+    /// Whether this processor can handle the shape and the antiderivative. Only the hard and sine
+    /// knee shapes and up to second-degree ADAA are vectorized.
+    pub fn supports(shape: ClipShape, antiderivative: &Antiderivative) -> bool {
+        matches!(shape, ClipShape::Hard | ClipShape::SineKnee)
+            && *antiderivative <= Antiderivative::SecondDegree
+    }
+
+    /// Forget the previous samples, as if the processor was just created.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.lanes);
+    }
+
+    /// Continue from the previous samples of `processors`, one per lane, so switching over from
+    /// them doesn't cause a transient.
+    pub fn load_history(&mut self, processors: &[Processor], antiderivative: &Antiderivative) {
+        for (lane, processor) in processors.iter().enumerate().take(self.lanes) {
+            self.x1[lane] = processor.x1;
+            self.x2[lane] = processor.x2;
+        }
+        self.update_history(antiderivative);
+    }
+
+    /// The opposite of [`load_history()`][Self::load_history()], for switching back to the scalar
+    /// processors.
+    pub fn store_history(&self, processors: &mut [Processor], antiderivative: &Antiderivative) {
+        for (lane, processor) in processors.iter_mut().enumerate().take(self.lanes) {
+            processor.x1 = self.x1[lane];
+            processor.x2 = self.x2[lane];
+            processor.x3 = self.x2[lane];
+            processor.update_history(antiderivative);
+        }
+    }
+
+    /// Process one sample for every lane in place. Lanes that aren't used are left untouched.
+    pub fn process(
+        &mut self,
+        x: &mut [f32; LANES],
+        curve: &Curve,
+        antiderivative: &Antiderivative,
+    ) {
+        use Antiderivative::*;
+
+        nih_debug_assert!(Self::supports(curve.shape, antiderivative));
+
+        // See `Processor::process()`
+        if *curve != self.curve {
+            self.curve = *curve;
+            self.halves = Halves::new(curve);
+            self.update_history(antiderivative);
+        }
+        let halves = self.halves;
+        let lanes = self.lanes;
+
+        let x0 = x.map(|x| x as f64);
+        let mut y = [0.0; LANES];
+
+        match antiderivative {
+            Off => {
+                for lane in 0..lanes {
+                    y[lane] = Point::new(x0[lane], &halves).func();
+                }
+            }
+            FirstDegree => {
+                let mut fallback = false;
+                for lane in 0..lanes {
+                    let ad1_x0 = Point::new(x0[lane], &halves).ad1();
+                    let delta = x0[lane] - self.x1[lane];
+
+                    fallback |= delta.abs() < TOL;
+                    y[lane] = (ad1_x0 - self.ad1_x1[lane]) / delta;
+
+                    self.ad1_x1[lane] = ad1_x0;
+                }
+
+                if fallback {
+                    for lane in 0..lanes {
+                        if (x0[lane] - self.x1[lane]).abs() < TOL {
+                            y[lane] = Point::new(0.5 * (x0[lane] + self.x1[lane]), &halves).func();
+                        }
+                    }
+                }
+
+                self.x1 = x0;
+            }
+            // Third-degree ADAA is not supported, see `supports()`
+            SecondDegree | ThirdDegree => {
+                let mut fallback = false;
+                let mut ad2_x0 = [0.0; LANES];
+                for lane in 0..lanes {
+                    ad2_x0[lane] = Point::new(x0[lane], &halves).ad2();
+
+                    let delta = x0[lane] - self.x1[lane];
+                    let d1 = if delta.abs() < TOL {
+                        Point::new(0.5 * (x0[lane] + self.x1[lane]), &halves).ad1()
+                    } else {
+                        (ad2_x0[lane] - self.ad2_x1[lane]) / delta
+                    };
+
+                    let span = x0[lane] - self.x2[lane];
+                    fallback |= span.abs() < TOL;
+                    y[lane] = 2.0 / span * (d1 - self.d2[lane]);
+
+                    self.d2[lane] = d1;
+                }
+
+                if fallback {
+                    for lane in 0..lanes {
+                        if (x0[lane] - self.x2[lane]).abs() >= TOL {
+                            continue;
+                        }
+
+                        let x_bar = 0.5 * (x0[lane] + self.x2[lane]);
+                        let delta = x_bar - self.x1[lane];
+
+                        y[lane] = if delta.abs() < TOL {
+                            Point::new(0.5 * (x_bar + self.x1[lane]), &halves).func()
+                        } else {
+                            let x_bar = Point::new(x_bar, &halves);
+
+                            2.0 / delta * (x_bar.ad1() + (self.ad2_x1[lane] - x_bar.ad2()) / delta)
+                        };
+                    }
+                }
+
+                self.x2 = self.x1;
+                self.x1 = x0;
+                self.ad2_x1 = ad2_x0;
+            }
+        }
+
+        for (x, y) in x.iter_mut().zip(y).take(lanes) {
+            *x = y as f32;
+        }
+    }
+
+    /// Evaluate the antiderivatives of the previous samples again using `self.halves`.
+    fn update_history(&mut self, antiderivative: &Antiderivative) {
+        use Antiderivative::*;
+
+        let (halves, lanes) = (self.halves, self.lanes);
+        match antiderivative {
+            Off => (),
+            FirstDegree => {
+                for lane in 0..lanes {
+                    self.ad1_x1[lane] = Point::new(self.x1[lane], &halves).ad1();
+                }
+            }
+            SecondDegree | ThirdDegree => {
+                for lane in 0..lanes {
+                    let (x1, x2) = (self.x1[lane], self.x2[lane]);
+                    let ad2_x2 = Point::new(x2, &halves).ad2();
+
+                    self.ad2_x1[lane] = Point::new(x1, &halves).ad2();
+                    self.d2[lane] = if (x1 - x2).abs() < TOL {
+                        Point::new(0.5 * (x1 + x2), &halves).ad1()
+                    } else {
+                        (self.ad2_x1[lane] - ad2_x2) / (x1 - x2)
+                    };
+                }
+            }
+        }
+    }
+}
+
+impl Halves {
+    fn new(curve: &Curve) -> Self {
+        let half = |softness: f64, scale: f64| {
+            let softness = curve.shape.softness(softness);
+
+            Half {
+                softness,
+                lower_bound: 1.0 - softness,
+                scale,
+            }
+        };
+
+        Self {
+            positive: half(curve.softness, 1.0),
+            negative: half(curve.negative_softness, curve.negative_threshold),
+        }
+    }
+}
+
+// These are `ClipShape::func()` and its antiderivatives for the sine knee, written in terms of
+// the lengths stored in `Point`. Past the knee, the antiderivatives continue as polynomials in the
+// excess, which is where the `excess` terms come from. The negative half is scaled the same way as
+// in `Curve`.
+impl Point {
+    #[inline(always)]
+    fn new(x: f64, halves: &Halves) -> Self {
+        let negative = x < 0.0;
+        let half = Half {
+            softness: select(negative, halves.negative.softness, halves.positive.softness),
+            lower_bound: select(
+                negative,
+                halves.negative.lower_bound,
+                halves.positive.lower_bound,
+            ),
+            scale: select(negative, halves.negative.scale, halves.positive.scale),
+        };
+
+        let abs_x = x.abs() / half.scale;
+        let knee = (abs_x - half.lower_bound).max(0.0);
+        // A hard clipper's knee has zero length, in which case `u` doesn't matter because it gets
+        // multiplied by the zero softness
+        let u = (knee / half.softness.max(f64::MIN_POSITIVE)).min(FRAC_PI_2);
+
+        Self {
+            sign: 1.0f64.copysign(x),
+            half,
+            linear: abs_x.min(half.lower_bound),
+            knee,
+            u,
+            excess: (knee - half.softness * FRAC_PI_2).max(0.0),
+        }
+    }
+
+    #[inline(always)]
+    fn func(&self) -> f64 {
+        let s = self.half.softness;
+
+        self.sign * self.half.scale * (self.linear + s * taylor::<1>(self.u))
+    }
+
+    #[inline(always)]
+    fn ad1(&self) -> f64 {
+        let Self {
+            linear: l,
+            knee: k,
+            excess: e,
+            ..
+        } = *self;
+        let s = self.half.softness;
+
+        self.half.scale.powi(2)
+            * (l * l / 2.0 + self.half.lower_bound * k + s * s * taylor::<2>(self.u) + s * e)
+    }
+
+    #[inline(always)]
+    fn ad2(&self) -> f64 {
+        let Self {
+            linear: l,
+            knee: k,
+            excess: e,
+            ..
+        } = *self;
+        let s = self.half.softness;
+
+        self.sign
+            * self.half.scale.powi(3)
+            * (l * l * l / 6.0
+                + l * l * k / 2.0
+                + l * k * k / 2.0
+                + s * s * s * taylor::<3>(self.u)
+                + s * s * e
+                + s * e * e / 2.0)
+    }
+}
+
+#[inline(always)]
+fn select(condition: bool, a: f64, b: f64) -> f64 {
+    if condition {
+        a
+    } else {
+        b
+    }
+}
+
+/// The alternating series `u^N/N! - u^(N+2)/(N+2)! + ...`, which is `sin(u)` for `N = 1`,
+/// `1 - cos(u)` for `N = 2`, and `u - sin(u)` for `N = 3`. Ten terms are accurate to within
+/// `f64`'s precision for `u` in `[0, pi/2]`. Summing the series directly also avoids the
+/// cancellation in the latter two for small `u`.
+#[inline(always)]
+fn taylor<const N: usize>(u: f64) -> f64 {
+    let u2 = u * u;
+
+    let mut total = 0.0;
+    for k in (0..10).rev() {
+        total = INVERSE_FACTORIALS[N + 2 * k] - u2 * total;
+    }
+
+    total * u.powi(N as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MAX_CHANNELS;
+    use std::f64::consts::PI;
+
+    /// A different sine for every channel that goes well past the threshold on both sides.
+    fn input(channel: usize, i: usize) -> f64 {
+        let frequency = 0.01 + 0.013 * channel as f64;
+        (1.2 + 0.1 * channel as f64) * (2.0 * PI * frequency * i as f64 + channel as f64).sin()
+    }
+
+    /// Changes halfway through, so the cached antiderivatives have to be evaluated again.
+    fn curve(shape: ClipShape, i: usize) -> Curve {
+        let softness = if i < 500 { 0.4 } else { 0.7 };
+        Curve {
+            shape,
+            softness,
+            negative_softness: softness * 0.5,
+            negative_threshold: 0.8,
+        }
+    }
+
+    #[test]
+    fn matches_scalar_processor() {
+        use Antiderivative::*;
+
+        for shape in [ClipShape::Hard, ClipShape::SineKnee] {
+            for antiderivative in [Off, FirstDegree, SecondDegree] {
+                for channels in [1, 2, 6, MAX_CHANNELS] {
+                    let mut reference = vec![Processor::default(); channels];
+                    let mut simd_processor = SimdProcessor::<MAX_CHANNELS>::new(channels);
+                    // Alternates between the SIMD and the scalar processors the same way
+                    // `ClipperChain` does when the shape changes
+                    let mut scalar = vec![Processor::default(); channels];
+
+                    for i in 0..1000 {
+                        let curve = curve(shape, i);
+                        let use_simd = (i / 100) % 2 == 0;
+                        if i % 100 == 0 && i > 0 {
+                            if use_simd {
+                                simd_processor.load_history(&scalar, &antiderivative);
+                            } else {
+                                simd_processor.store_history(&mut scalar, &antiderivative);
+                            }
+                        }
+
+                        let mut lane = [f32::NAN; MAX_CHANNELS];
+                        for (channel, sample) in lane.iter_mut().enumerate().take(channels) {
+                            *sample = input(channel, i) as f32;
+                        }
+                        if use_simd {
+                            simd_processor.process(&mut lane, &curve, &antiderivative);
+                        } else {
+                            for (sample, processor) in lane.iter_mut().zip(scalar.iter_mut()) {
+                                *sample = processor.process(*sample as f64, &curve, &antiderivative)
+                                    as f32;
+                            }
+                        }
+
+                        for (channel, processor) in reference.iter_mut().enumerate() {
+                            let x = input(channel, i) as f32 as f64;
+                            let expected = processor.process(x, &curve, &antiderivative);
+                            assert!(
+                                (lane[channel] as f64 - expected).abs() < 1e-4,
+                                "{shape:?}, {antiderivative:?}, {channels} channels, channel \
+                                 {channel}, sample {i}: {} != {expected}",
+                                lane[channel]
+                            );
+                        }
+                        assert!(lane[channels..].iter().all(|sample| sample.is_nan()));
+                    }
+                }
+            }
+        }
+    }
+}
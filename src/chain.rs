@@ -0,0 +1,396 @@
+use nih_plug::util::db_to_gain_fast;
+
+use crate::{
+    antialiasing::{ClipShape, Curve, Processor, SimdProcessor},
+    filters::{Biquad, DelayLine, Emphasis},
+    oversampling::{FilterPhase, FilterQuality, Lanczos3Oversampler},
+    Antiderivative, BLOCK_SIZE, MAX_CHANNELS, MAX_OVERSAMPLED_BLOCK_SIZE, MAX_OVERSAMPLING_FACTOR,
+};
+
+/// Everything that's needed to clip the signal with one oversampling factor and antiderivative
+/// order, for every channel. `Klyp` uses one of these per band, and runs two sets of them side by
+/// side and crossfades between them when those settings change, so the switch doesn't click.
+pub struct ClipperChain {
+    oversampling: usize,
+    antiderivative: Antiderivative,
+    processors: Vec<Processor>,
+    /// Used instead of `processors` whenever it supports the current settings.
+    simd_processor: SimdProcessor<MAX_CHANNELS>,
+    /// Whether the last block was clipped by `simd_processor`. The history is handed over between
+    /// the two when this changes.
+    used_simd: bool,
+    oversamplers: Vec<Lanczos3Oversampler>,
+    /// Tilt each channel before it's clipped, and undo the tilt afterwards.
+    emphasis: Vec<Emphasis>,
+    /// The de-emphasis coefficients for the most recent samples, as a ring buffer. The
+    /// de-emphasis filter sees every sample the chain's latency after the pre-emphasis filter
+    /// did, so it reads the coefficients from that long ago. That keeps the two nulling while the
+    /// emphasis is automated.
+    de_emphasis_history: Vec<Biquad>,
+    /// The next sample's write position in `de_emphasis_history`.
+    de_emphasis_pos: usize,
+    /// Set after a reset. Since there's no older signal left to de-emphasize, the whole history is
+    /// then filled with the next block's coefficients.
+    de_emphasis_cleared: bool,
+    /// Delays the output in constant latency mode, so the total latency always matches the worst
+    /// case.
+    latency_padding: Vec<DelayLine>,
+    padding: u32,
+    /// The oversampled signal, with one lane per channel.
+    lanes: Box<[[f32; MAX_CHANNELS]; MAX_OVERSAMPLED_BLOCK_SIZE]>,
+    /// The previous oversampled samples before clipping, used to delay the bypassed channels.
+    bypass_history: [[f32; MAX_CHANNELS]; 3],
+    /// The previous oversampled samples clipped with the linked gain, used to delay them the same
+    /// way.
+    link_history: [[f32; MAX_CHANNELS]; 3],
+}
+
+/// The smoothed parameter values for a block. These are computed at the oversampled rate of the
+/// chain that's being switched to, which can differ from a chain's own rate while crossfading.
+pub struct BlockParameters<'a> {
+    /// The oversampling factor the parameters were smoothed at.
+    pub oversampling: usize,
+    pub shape: ClipShape,
+    /// The gain in decibels. This is applied before upsampling, so it's at the base sample rate.
+    pub gain: &'a [f32],
+    pub threshold: &'a [f32],
+    pub softness: &'a [f32],
+    pub negative_threshold: &'a [f32],
+    pub negative_softness: &'a [f32],
+    pub bias: &'a [f32],
+    /// The pre- and de-emphasis filters for the block, which are only updated once per block.
+    pub emphasis: Emphasis,
+    /// How much the channels are clipped with a shared gain instead of on their own, from 0 to 1.
+    pub stereo_link: &'a [f32],
+    /// The channels the stereo link applies to.
+    pub linked: [bool; MAX_CHANNELS],
+    /// Channels that aren't clipped, and don't get the gain or the emphasis either. They still go
+    /// through the oversampling filters, so they stay aligned with the clipped channels.
+    pub bypassed: [bool; MAX_CHANNELS],
+}
+
+impl ClipperChain {
+    /// Create a chain for `channels` channels that can pad the latency by up to `max_padding`
+    /// samples. The chain starts out without oversampling and ADAA.
+    pub fn new(channels: usize, max_padding: u32) -> Self {
+        Self {
+            oversampling: 0,
+            antiderivative: Antiderivative::Off,
+            processors: vec![Processor::default(); channels],
+            simd_processor: SimdProcessor::new(channels),
+            used_simd: false,
+            oversamplers: (0..channels)
+                .map(|_| Lanczos3Oversampler::new(BLOCK_SIZE, MAX_OVERSAMPLING_FACTOR))
+                .collect(),
+            emphasis: vec![Emphasis::default(); channels],
+            de_emphasis_history: vec![Biquad::default(); max_padding as usize + 1],
+            de_emphasis_pos: 0,
+            de_emphasis_cleared: true,
+            latency_padding: vec![DelayLine::new(max_padding as usize); channels],
+            padding: 0,
+            lanes: Box::new([[0.0; MAX_CHANNELS]; MAX_OVERSAMPLED_BLOCK_SIZE]),
+            bypass_history: [[0.0; MAX_CHANNELS]; 3],
+            link_history: [[0.0; MAX_CHANNELS]; 3],
+        }
+    }
+
+    /// Clear the filters', the antiderivatives' and the padding's histories.
+    pub fn reset(&mut self) {
+        for processor in &mut self.processors {
+            processor.reset();
+        }
+        self.simd_processor.reset();
+        for oversampler in &mut self.oversamplers {
+            oversampler.reset();
+        }
+        for emphasis in &mut self.emphasis {
+            emphasis.reset();
+        }
+        self.de_emphasis_cleared = true;
+        for delay_line in &mut self.latency_padding {
+            delay_line.reset();
+        }
+        self.bypass_history = [[0.0; MAX_CHANNELS]; 3];
+        self.link_history = [[0.0; MAX_CHANNELS]; 3];
+    }
+
+    pub fn oversampling(&self) -> usize {
+        self.oversampling
+    }
+
+    pub fn antiderivative(&self) -> Antiderivative {
+        self.antiderivative
+    }
+
+    /// Switch to another oversampling factor and antiderivative. This resets the chain, since the
+    /// filters' and ADAA's histories wouldn't match the new settings.
+    pub fn configure(&mut self, oversampling: usize, antiderivative: Antiderivative) {
+        self.oversampling = oversampling;
+        self.antiderivative = antiderivative;
+        self.reset();
+    }
+
+    /// Switch the oversamplers to other filters. The oversamplers are reset when this changes the
+    /// filters.
+    pub fn set_filters(&mut self, quality: FilterQuality, phase: FilterPhase) {
+        for oversampler in &mut self.oversamplers {
+            oversampler.set_filters(quality, phase);
+        }
+    }
+
+    /// The chain's latency in samples, without the padding.
+    pub fn latency(&self) -> u32 {
+        self.latency_for(self.oversampling, &self.antiderivative)
+    }
+
+    /// The highest latency any oversampling factor and antiderivative can have with the current
+    /// filters. Third-degree ADAA always has the most latency.
+    pub fn max_latency(&self) -> u32 {
+        (0..=MAX_OVERSAMPLING_FACTOR)
+            .map(|oversampling| self.latency_for(oversampling, &Antiderivative::ThirdDegree))
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn latency_for(&self, oversampling: usize, antiderivative: &Antiderivative) -> u32 {
+        let mut latency = 0;
+
+        if let Some(oversampler) = self.oversamplers.first() {
+            latency += oversampler.latency(oversampling);
+        }
+
+        latency += antiderivative.latency(oversampling);
+
+        latency
+    }
+
+    /// The chain's latency including the padding.
+    pub fn total_latency(&self) -> u32 {
+        self.latency() + self.padding
+    }
+
+    /// Delay the output by an additional `padding` samples.
+    pub fn set_padding(&mut self, padding: u32) {
+        self.padding = padding;
+        for delay_line in &mut self.latency_padding {
+            delay_line.set_delay(padding as usize);
+        }
+    }
+
+    /// Clip the first `len` samples of each channel in `channels` in place.
+    pub fn process(
+        &mut self,
+        channels: &mut [[f32; BLOCK_SIZE]],
+        len: usize,
+        parameters: &BlockParameters,
+    ) {
+        let BlockParameters {
+            shape,
+            gain,
+            threshold,
+            softness,
+            negative_threshold,
+            negative_softness,
+            bias,
+            stereo_link,
+            linked,
+            bypassed,
+            ..
+        } = *parameters;
+
+        let oversampling = self.oversampling;
+        let samples_upscaled = len << oversampling;
+        let use_simd = SimdProcessor::<MAX_CHANNELS>::supports(shape, &self.antiderivative);
+        if use_simd != self.used_simd {
+            if use_simd {
+                self.simd_processor
+                    .load_history(&self.processors, &self.antiderivative);
+            } else {
+                self.simd_processor
+                    .store_history(&mut self.processors, &self.antiderivative);
+            }
+            self.used_simd = use_simd;
+        }
+        // For a linear curve, ADAA reduces to a moving average over this many samples. This is
+        // used to delay the signals that don't go through the processors by as much as the
+        // antiderivatives delay the ones that do.
+        let adaa_taps = match self.antiderivative {
+            Antiderivative::Off => 1,
+            Antiderivative::FirstDegree => 2,
+            Antiderivative::SecondDegree => 3,
+            Antiderivative::ThirdDegree => 4,
+        };
+        let channel_count = self.oversamplers.len();
+        // While the link is off and its smoother isn't ramping, only the last few linked samples
+        // of the block are computed. That's all the history the moving average needs once the
+        // link gets turned up again.
+        let link_active = stereo_link[..len << parameters.oversampling]
+            .iter()
+            .any(|amount| *amount > 0.0);
+        let link_start = samples_upscaled.saturating_sub(self.link_history.len());
+
+        let mut de_emphasis = [Biquad::default(); BLOCK_SIZE];
+        let history_len = self.de_emphasis_history.len();
+        let de_emphasis_delay = self.latency() as usize;
+        if self.de_emphasis_cleared {
+            self.de_emphasis_history.fill(parameters.emphasis.de);
+            self.de_emphasis_cleared = false;
+        }
+        for de_emphasis in &mut de_emphasis[..len] {
+            self.de_emphasis_history[self.de_emphasis_pos] = parameters.emphasis.de;
+            *de_emphasis = self.de_emphasis_history
+                [(self.de_emphasis_pos + history_len - de_emphasis_delay) % history_len];
+            self.de_emphasis_pos = (self.de_emphasis_pos + 1) % history_len;
+        }
+
+        // The oversampled signals of all channels are gathered into lanes, so the clipping can
+        // process every channel at once
+        for (channel, (samples, (oversampler, emphasis))) in channels
+            .iter_mut()
+            .zip(self.oversamplers.iter_mut().zip(self.emphasis.iter_mut()))
+            .enumerate()
+        {
+            emphasis.pre.set_coefficients(&parameters.emphasis.pre);
+
+            // The bypassed channels skip the gain and the emphasis, so they come out unchanged.
+            // They're still scaled by the threshold like the others, which is undone afterwards.
+            let samples = &mut samples[..len];
+            for (i, sample) in samples.iter_mut().enumerate() {
+                if !bypassed[channel] {
+                    let gain = unsafe { db_to_gain_fast(*gain.get_unchecked(i)) };
+                    *sample = emphasis.pre.process(*sample) * gain;
+                }
+                *sample /= unsafe { threshold.get_unchecked(i) };
+            }
+            let upsampled = oversampler.upsample_only(samples, oversampling);
+            for (lane, sample) in self.lanes.iter_mut().zip(upsampled.iter()) {
+                lane[channel] = *sample;
+            }
+        }
+
+        for (i, lane) in self.lanes.iter_mut().enumerate().take(samples_upscaled) {
+            // The parameters may have been smoothed at another rate, see `BlockParameters`
+            let parameter_i = (i << parameters.oversampling) >> oversampling;
+            // The thresholds are only read at the base sample rate
+            let base_i = i >> oversampling;
+            let curve = unsafe {
+                Curve {
+                    shape,
+                    softness: *softness.get_unchecked(parameter_i) as f64,
+                    negative_softness: *negative_softness.get_unchecked(parameter_i) as f64,
+                    negative_threshold: (*negative_threshold.get_unchecked(base_i)
+                        / *threshold.get_unchecked(base_i))
+                        as f64,
+                }
+            };
+
+            // The bias shifts the signal along the curve. Subtracting the curve's response to
+            // the bias on its own afterwards keeps silence silent.
+            let bias = unsafe { *bias.get_unchecked(parameter_i) } as f64;
+            let offset = curve.func(bias);
+            let unclipped = *lane;
+
+            if use_simd {
+                for sample in lane.iter_mut().take(channel_count) {
+                    *sample = (*sample as f64 + bias) as f32;
+                }
+                self.simd_processor
+                    .process(lane, &curve, &self.antiderivative);
+                for sample in lane.iter_mut().take(channel_count) {
+                    *sample = (*sample as f64 - offset) as f32;
+                }
+            } else {
+                for (sample, processor) in lane.iter_mut().zip(self.processors.iter_mut()) {
+                    *sample =
+                        (processor.process(*sample as f64 + bias, &curve, &self.antiderivative)
+                            - offset) as f32;
+                }
+            }
+
+            // Linking the channels clips them all with the gain the curve applies to the one
+            // that reaches furthest into it, which keeps the stereo image in place. This gain
+            // isn't antialiased by the antiderivatives, so it relies on the oversampling.
+            if link_active || i >= link_start {
+                let stereo_link = unsafe { *stereo_link.get_unchecked(parameter_i) };
+                let is_linked = |channel: usize| linked[channel] && !bypassed[channel];
+                let level = (0..channel_count)
+                    .filter(|channel| is_linked(*channel))
+                    .map(|channel| curve.level(unclipped[channel] as f64 + bias))
+                    .fold(0.0, f64::max);
+
+                let mut linked_samples = [0.0; MAX_CHANNELS];
+                for (channel, (sample, linked_sample)) in lane
+                    .iter_mut()
+                    .zip(linked_samples.iter_mut())
+                    .enumerate()
+                    .take(channel_count)
+                {
+                    let x = unclipped[channel] as f64 + bias;
+                    *linked_sample = (curve.gain(x, level) * x - offset) as f32;
+
+                    if stereo_link > 0.0 && is_linked(channel) {
+                        let delayed =
+                            moving_average(&self.link_history, adaa_taps, channel, *linked_sample);
+                        *sample += (delayed - *sample) * stereo_link;
+                    }
+                }
+                self.link_history.rotate_right(1);
+                self.link_history[0] = linked_samples;
+            }
+
+            // The processors still see the bypassed channels, which keeps their histories
+            // continuous in case they stop being bypassed
+            for (channel, (sample, bypassed)) in lane.iter_mut().zip(bypassed.iter()).enumerate() {
+                if *bypassed {
+                    *sample = moving_average(
+                        &self.bypass_history,
+                        adaa_taps,
+                        channel,
+                        unclipped[channel],
+                    );
+                }
+            }
+            self.bypass_history.rotate_right(1);
+            self.bypass_history[0] = unclipped;
+        }
+
+        let lanes = &*self.lanes;
+        for (channel, (samples, ((oversampler, emphasis), delay_line))) in channels
+            .iter_mut()
+            .zip(
+                self.oversamplers
+                    .iter_mut()
+                    .zip(self.emphasis.iter_mut())
+                    .zip(self.latency_padding.iter_mut()),
+            )
+            .enumerate()
+        {
+            let samples = &mut samples[..len];
+            oversampler.downsample_only(samples, oversampling, |upsampled| {
+                for (sample, lane) in upsampled.iter_mut().zip(lanes.iter()) {
+                    *sample = lane[channel];
+                }
+            });
+            for (i, sample) in samples.iter_mut().enumerate() {
+                *sample *= unsafe { threshold.get_unchecked(i) };
+                if !bypassed[channel] {
+                    emphasis.de.set_coefficients(&de_emphasis[i]);
+                    *sample = emphasis.de.process(*sample);
+                }
+                *sample = delay_line.process(*sample);
+            }
+        }
+    }
+}
+
+/// The average of `x` and the previous `taps - 1` samples in `history` for one channel, where
+/// `history[0]` holds the most recent samples.
+#[inline]
+fn moving_average(history: &[[f32; MAX_CHANNELS]; 3], taps: usize, channel: usize, x: f32) -> f32 {
+    let previous = history[..taps - 1]
+        .iter()
+        .map(|samples| samples[channel])
+        .sum::<f32>();
+
+    (x + previous) / taps as f32
+}
@@ -0,0 +1,830 @@
+use nih_plug::{debug::*, prelude::Enum};
+use realfft::{num_complex::Complex, ComplexToReal, RealFftPlanner, RealToComplex};
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use crate::{
+    filters::{Biquad, DelayLine},
+    BLOCK_SIZE, MAX_CHANNELS,
+};
+
+/// The most bands the signal can be split into.
+pub const MAX_BANDS: usize = 4;
+/// The length of the linear-phase crossovers' kernels, in seconds. The transition bands get
+/// narrower the longer the kernels are, but the latency is half this long.
+const LINEAR_PHASE_LENGTH: f32 = 0.04;
+/// The linear-phase kernels are applied one block of this many samples at a time, see
+/// [`LinearPhase`]. This adds one block of latency on top of the kernels' own.
+const LINEAR_PHASE_BLOCK_SIZE: usize = 128;
+
+/// How the signal is split into bands.
+#[derive(Enum, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrossoverType {
+    /// Fourth-order Linkwitz-Riley filters. These don't add latency, but the bands' sum is shifted
+    /// in phase around the crossover frequencies.
+    #[id = "linkwitz_riley"]
+    #[name = "Linkwitz-Riley"]
+    #[default]
+    LinkwitzRiley,
+    /// Windowed sinc FIR filters. The bands sum back to the input without any phase shift, but
+    /// they add latency and use more CPU, especially with many channels.
+    #[id = "linear_phase"]
+    #[name = "Linear-Phase"]
+    LinearPhase,
+}
+
+/// Splits every channel into up to [`MAX_BANDS`] bands. The bands add back up to the input when
+/// they're left alone, up to an allpass response for the Linkwitz-Riley filters or a delay for
+/// the linear-phase filters.
+pub struct Crossover {
+    sample_rate: f32,
+    crossover_type: CrossoverType,
+    bands: usize,
+    /// The crossover frequencies as they were set, including the ones that aren't used.
+    requested_frequencies: [f32; MAX_BANDS - 1],
+    /// The first `bands - 1` requested frequencies in ascending order. The rest is unused.
+    frequencies: [f32; MAX_BANDS - 1],
+    linkwitz_riley: Vec<LinkwitzRiley>,
+    linear_phase: LinearPhase,
+}
+
+/// The Linkwitz-Riley filters for one channel. The signal is split off band by band from the
+/// bottom up, and the lower bands go through allpasses matching the higher crossovers, so every
+/// band ends up with the same phase response.
+#[derive(Debug, Clone, Default)]
+struct LinkwitzRiley {
+    /// Two cascaded Butterworth lowpasses per crossover.
+    lowpasses: [[Biquad; 2]; MAX_BANDS - 1],
+    /// Two cascaded Butterworth highpasses per crossover.
+    highpasses: [[Biquad; 2]; MAX_BANDS - 1],
+    /// `allpasses[band][crossover]` compensates `band` for a higher crossover's phase shift.
+    allpasses: [[Biquad; MAX_BANDS - 1]; MAX_BANDS - 1],
+}
+
+/// Splits off the lows below a frequency with a fourth-order Linkwitz-Riley crossover, so they can
+/// skip the clipping. The lows go through the same phase shifts as the rest of the signal, so the
+/// two add back up to an allpass response.
+pub struct LowSplit {
+    sample_rate: f32,
+    frequency: f32,
+    /// The frequencies of the [`Crossover`]'s phase shifts that the lows need to match.
+    allpass_frequencies: Vec<f32>,
+    channels: Vec<LowSplitChannel>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct LowSplitChannel {
+    lowpasses: [Biquad; 2],
+    highpasses: [Biquad; 2],
+    allpasses: [Biquad; MAX_BANDS - 1],
+}
+
+/// Complementary linear-phase filters. Every crossover is a lowpass kernel of the same length, and
+/// the bands are the differences between consecutive lowpasses, so they add up to the delayed
+/// input exactly.
+///
+/// The kernels are thousands of taps long, which is far too slow to convolve with directly. They're
+/// applied with uniformly partitioned overlap-save convolution instead. The kernels are split into
+/// [`LINEAR_PHASE_BLOCK_SIZE`] long partitions, and every block of input is transformed once and
+/// kept around for as many blocks as there are partitions. Each crossover's output for a block is
+/// then the sum of its partitions' spectra multiplied with the spectra of the blocks they line up
+/// with, which takes one inverse transform per crossover.
+///
+/// Moving a crossover only takes effect at the start of the next block, so its kernel is rebuilt
+/// at most once per block. The old and new kernels' outputs are crossfaded over that block, since
+/// switching between them outright would click.
+struct LinearPhase {
+    /// The length of the lowpass kernels, which is odd.
+    taps: usize,
+    /// The cutoffs the kernels should have, as fractions of the sample rate.
+    cutoffs: [f32; MAX_BANDS - 1],
+    /// The cutoffs the current `partitions` were computed for.
+    kernel_cutoffs: [f32; MAX_BANDS - 1],
+    /// The spectra of every crossover's kernel partitions, zero-padded to two blocks and scaled
+    /// so the inverse transforms don't need to be normalized.
+    partitions: [Vec<Vec<Complex<f32>>>; MAX_BANDS - 1],
+    /// The partitions the crossovers had before they last moved, which are faded out during the
+    /// block after that.
+    previous_partitions: [Vec<Vec<Complex<f32>>>; MAX_BANDS - 1],
+    channels: Vec<LinearPhaseChannel>,
+    /// The position within the current block, which is the same for every channel.
+    pos: usize,
+    /// Where the most recent block's spectrum is stored in the channels' `spectra`.
+    newest: usize,
+    forward: Arc<dyn RealToComplex<f32>>,
+    inverse: Arc<dyn ComplexToReal<f32>>,
+    /// Holds one crossover's full kernel while its partitions are computed.
+    kernel: Vec<f32>,
+    /// Scratch buffers for the transforms, two blocks long in the time domain.
+    time: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+}
+
+struct LinearPhaseChannel {
+    /// The previous block of input followed by the current one.
+    input: Vec<f32>,
+    /// The spectra of the most recent blocks of input, one per kernel partition, as a ring buffer.
+    spectra: Vec<Vec<Complex<f32>>>,
+    /// The crossovers' lowpassed signals for the previous block, which are played back during the
+    /// current one.
+    lowpassed: [Vec<f32>; MAX_BANDS - 1],
+    /// Delays the input to line up with the lowpasses, for the top band.
+    delay_line: DelayLine,
+}
+
+impl Crossover {
+    /// Create a crossover for `channels` channels. It starts out with a single band, which leaves
+    /// the signal alone.
+    pub fn new(sample_rate: f32, channels: usize) -> Self {
+        let mut crossover = Self {
+            sample_rate,
+            crossover_type: CrossoverType::default(),
+            bands: 1,
+            requested_frequencies: [120.0, 1000.0, 5000.0],
+            frequencies: [120.0, 1000.0, 5000.0],
+            linkwitz_riley: vec![LinkwitzRiley::default(); channels],
+            linear_phase: LinearPhase::new(sample_rate, channels),
+        };
+        crossover.update_coefficients();
+
+        crossover
+    }
+
+    /// Clear the filters' histories.
+    pub fn reset(&mut self) {
+        for linkwitz_riley in &mut self.linkwitz_riley {
+            linkwitz_riley.reset();
+        }
+        self.linear_phase.reset();
+    }
+
+    pub fn bands(&self) -> usize {
+        self.bands
+    }
+
+    /// Switch to another number of bands or crossover type. Returns whether anything changed, in
+    /// which case the filters have been reset.
+    pub fn set_bands(&mut self, bands: usize, crossover_type: CrossoverType) -> bool {
+        let bands = bands.clamp(1, MAX_BANDS);
+        if bands == self.bands && crossover_type == self.crossover_type {
+            return false;
+        }
+
+        self.bands = bands;
+        self.crossover_type = crossover_type;
+        self.update_frequencies();
+        self.update_coefficients();
+        self.reset();
+
+        true
+    }
+
+    /// Move the crossovers to other frequencies, in Hz. The ones in use are sorted, so the bands
+    /// can't overlap, while the unused ones can't move the others around. Unlike
+    /// [`set_bands()`][Self::set_bands()], this keeps the filters' histories.
+    pub fn set_frequencies(&mut self, frequencies: [f32; MAX_BANDS - 1]) {
+        self.requested_frequencies = frequencies;
+
+        if self.update_frequencies() {
+            self.update_coefficients();
+        }
+    }
+
+    /// The crossover frequencies in use, in ascending order.
+    pub fn frequencies(&self) -> &[f32] {
+        &self.frequencies[..self.bands - 1]
+    }
+
+    /// The delay the crossover adds to the signal, in samples.
+    pub fn latency(&self) -> u32 {
+        if self.bands > 1 && self.crossover_type == CrossoverType::LinearPhase {
+            self.linear_phase.latency()
+        } else {
+            0
+        }
+    }
+
+    /// The highest latency any number of bands and crossover type can have.
+    pub fn max_latency(&self) -> u32 {
+        self.linear_phase.latency()
+    }
+
+    /// The frequencies of the allpass responses the bands add up to. The linear-phase filters
+    /// don't shift the phase, so they only delay the signal by [`latency()`][Self::latency()].
+    pub fn allpass_frequencies(&self) -> &[f32] {
+        match self.crossover_type {
+            CrossoverType::LinkwitzRiley => self.frequencies(),
+            CrossoverType::LinearPhase => &[],
+        }
+    }
+
+    /// Sort and clamp the requested frequencies that are in use. Returns whether they changed.
+    fn update_frequencies(&mut self) -> bool {
+        let mut frequencies = self.requested_frequencies;
+        let crossovers = &mut frequencies[..self.bands - 1];
+        for frequency in crossovers.iter_mut() {
+            *frequency = frequency.clamp(10.0, self.sample_rate * 0.45);
+        }
+        crossovers.sort_by(f32::total_cmp);
+
+        let changed = frequencies != self.frequencies;
+        self.frequencies = frequencies;
+
+        changed
+    }
+
+    /// Only the filters that are currently in use are updated. Switching to others goes through
+    /// [`set_bands()`][Self::set_bands()], which calls this again.
+    fn update_coefficients(&mut self) {
+        let crossovers = &self.frequencies[..self.bands - 1];
+
+        match self.crossover_type {
+            CrossoverType::LinkwitzRiley => {
+                for linkwitz_riley in &mut self.linkwitz_riley {
+                    linkwitz_riley.set_frequencies(self.sample_rate, crossovers);
+                }
+            }
+            CrossoverType::LinearPhase => {
+                for (crossover, frequency) in crossovers.iter().enumerate() {
+                    self.linear_phase
+                        .set_cutoff(crossover, frequency / self.sample_rate);
+                }
+            }
+        }
+    }
+
+    /// Split the first `len` samples of each channel in `channels` into `bands`, which needs to
+    /// hold exactly [`bands()`][Self::bands()] sets of channels, from the lowest band up.
+    pub fn process(
+        &mut self,
+        channels: &[[f32; BLOCK_SIZE]],
+        len: usize,
+        bands: &mut [[[f32; BLOCK_SIZE]; MAX_CHANNELS]],
+    ) {
+        let crossovers = self.bands - 1;
+
+        match self.crossover_type {
+            CrossoverType::LinkwitzRiley => {
+                for (channel, (samples, linkwitz_riley)) in channels
+                    .iter()
+                    .zip(self.linkwitz_riley.iter_mut())
+                    .enumerate()
+                {
+                    for (i, sample) in samples[..len].iter().enumerate() {
+                        let mut split = [0.0; MAX_BANDS];
+                        linkwitz_riley.process(*sample, crossovers, &mut split);
+
+                        for (band, split) in bands.iter_mut().zip(split) {
+                            band[channel][i] = split;
+                        }
+                    }
+                }
+            }
+            CrossoverType::LinearPhase => {
+                let linear_phase = &mut self.linear_phase;
+
+                for i in 0..len {
+                    let pos = linear_phase.pos;
+                    for (channel, (samples, state)) in channels
+                        .iter()
+                        .zip(linear_phase.channels.iter_mut())
+                        .enumerate()
+                    {
+                        state.input[LINEAR_PHASE_BLOCK_SIZE + pos] = samples[i];
+
+                        // Each band is what's left between its lower and upper crossovers'
+                        // lowpasses. The top band uses the delayed input as its upper lowpass.
+                        let mut lower = 0.0;
+                        for (band, lowpassed) in bands
+                            .iter_mut()
+                            .zip(state.lowpassed.iter())
+                            .take(crossovers)
+                        {
+                            band[channel][i] = lowpassed[pos] - lower;
+                            lower = lowpassed[pos];
+                        }
+                        bands[crossovers][channel][i] =
+                            state.delay_line.process(samples[i]) - lower;
+                    }
+
+                    linear_phase.pos += 1;
+                    if linear_phase.pos == LINEAR_PHASE_BLOCK_SIZE {
+                        linear_phase.process_block(crossovers);
+                        linear_phase.pos = 0;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl LowSplit {
+    /// Create a split for `channels` channels.
+    pub fn new(sample_rate: f32, channels: usize) -> Self {
+        Self {
+            sample_rate,
+            frequency: 0.0,
+            allpass_frequencies: Vec::with_capacity(MAX_BANDS - 1),
+            channels: vec![LowSplitChannel::default(); channels],
+        }
+    }
+
+    /// Clear the filters' histories.
+    pub fn reset(&mut self) {
+        for channel in &mut self.channels {
+            channel
+                .lowpasses
+                .iter_mut()
+                .chain(channel.highpasses.iter_mut())
+                .chain(channel.allpasses.iter_mut())
+                .for_each(Biquad::reset);
+        }
+    }
+
+    /// Move the split to another frequency in Hz, and match the phase shifts at
+    /// `allpass_frequencies`, see [`Crossover::allpass_frequencies()`]. This keeps the filters'
+    /// histories.
+    pub fn set_frequencies(&mut self, frequency: f32, allpass_frequencies: &[f32]) {
+        let frequency = frequency.clamp(10.0, self.sample_rate * 0.45);
+        if frequency == self.frequency && allpass_frequencies == self.allpass_frequencies {
+            return;
+        }
+
+        self.frequency = frequency;
+        self.allpass_frequencies.clear();
+        self.allpass_frequencies
+            .extend_from_slice(allpass_frequencies);
+
+        let lowpass = Biquad::lowpass(self.sample_rate, frequency);
+        let highpass = Biquad::highpass(self.sample_rate, frequency);
+        for channel in &mut self.channels {
+            for filter in &mut channel.lowpasses {
+                filter.set_coefficients(&lowpass);
+            }
+            for filter in &mut channel.highpasses {
+                filter.set_coefficients(&highpass);
+            }
+            for (filter, frequency) in channel.allpasses.iter_mut().zip(allpass_frequencies) {
+                filter.set_coefficients(&Biquad::allpass(self.sample_rate, *frequency));
+            }
+        }
+    }
+
+    /// Split the first `len` samples of each channel in `channels`, moving the lows to `lows`.
+    /// `mix` holds how much of the lows to split off for each sample, from 0 to 1, which lets the
+    /// split be faded in and out. The lows still need to be delayed to line up with the highs
+    /// before they're added back. The filters keep running either way, so the split can be
+    /// enabled without having to settle first.
+    pub fn process(
+        &mut self,
+        channels: &mut [[f32; BLOCK_SIZE]],
+        len: usize,
+        lows: &mut [[f32; BLOCK_SIZE]],
+        mix: &[f32],
+    ) {
+        let allpasses = self.allpass_frequencies.len();
+
+        for ((samples, lows), channel) in channels
+            .iter_mut()
+            .zip(lows.iter_mut())
+            .zip(self.channels.iter_mut())
+        {
+            let [lowpass_1, lowpass_2] = &mut channel.lowpasses;
+            let [highpass_1, highpass_2] = &mut channel.highpasses;
+
+            for ((sample, low), mix) in samples[..len]
+                .iter_mut()
+                .zip(lows[..len].iter_mut())
+                .zip(mix.iter())
+            {
+                let mut lowpassed = lowpass_2.process(lowpass_1.process(*sample));
+                for allpass in &mut channel.allpasses[..allpasses] {
+                    lowpassed = allpass.process(lowpassed);
+                }
+                *low = lowpassed * mix;
+
+                // Halfway through a fade, half of the full signal and half of the highs get
+                // clipped, and half of the lows skip the clipping
+                let highpassed = highpass_2.process(highpass_1.process(*sample));
+                *sample += (highpassed - *sample) * mix;
+            }
+        }
+    }
+}
+
+impl LinkwitzRiley {
+    fn reset(&mut self) {
+        self.lowpasses
+            .iter_mut()
+            .chain(self.highpasses.iter_mut())
+            .flatten()
+            .chain(self.allpasses.iter_mut().flatten())
+            .for_each(Biquad::reset);
+    }
+
+    fn set_frequencies(&mut self, sample_rate: f32, crossovers: &[f32]) {
+        for (crossover, frequency) in crossovers.iter().enumerate() {
+            let lowpass = Biquad::lowpass(sample_rate, *frequency);
+            let highpass = Biquad::highpass(sample_rate, *frequency);
+            let allpass = Biquad::allpass(sample_rate, *frequency);
+
+            for filter in &mut self.lowpasses[crossover] {
+                filter.set_coefficients(&lowpass);
+            }
+            for filter in &mut self.highpasses[crossover] {
+                filter.set_coefficients(&highpass);
+            }
+            for allpasses in &mut self.allpasses[..crossover] {
+                allpasses[crossover].set_coefficients(&allpass);
+            }
+        }
+    }
+
+    #[inline]
+    fn process(&mut self, x: f32, crossovers: usize, bands: &mut [f32; MAX_BANDS]) {
+        let mut rest = x;
+
+        for (crossover, band) in bands.iter_mut().enumerate().take(crossovers) {
+            let [lowpass_1, lowpass_2] = &mut self.lowpasses[crossover];
+            let [highpass_1, highpass_2] = &mut self.highpasses[crossover];
+
+            *band = lowpass_2.process(lowpass_1.process(rest));
+            for allpass in &mut self.allpasses[crossover][crossover + 1..crossovers] {
+                *band = allpass.process(*band);
+            }
+            rest = highpass_2.process(highpass_1.process(rest));
+        }
+
+        bands[crossovers] = rest;
+    }
+}
+
+impl LinearPhase {
+    fn new(sample_rate: f32, channels: usize) -> Self {
+        let taps = (LINEAR_PHASE_LENGTH * sample_rate) as usize | 1;
+        let partitions = taps.div_ceil(LINEAR_PHASE_BLOCK_SIZE);
+
+        let mut planner = RealFftPlanner::<f32>::new();
+        let forward = planner.plan_fft_forward(LINEAR_PHASE_BLOCK_SIZE * 2);
+        let inverse = planner.plan_fft_inverse(LINEAR_PHASE_BLOCK_SIZE * 2);
+        let scratch_len = forward.get_scratch_len().max(inverse.get_scratch_len());
+        let spectrum = forward.make_output_vec();
+
+        let mut linear_phase = Self {
+            taps,
+            cutoffs: [0.25; MAX_BANDS - 1],
+            kernel_cutoffs: [0.25; MAX_BANDS - 1],
+            partitions: std::array::from_fn(|_| vec![spectrum.clone(); partitions]),
+            previous_partitions: std::array::from_fn(|_| vec![spectrum.clone(); partitions]),
+            channels: (0..channels)
+                .map(|_| {
+                    let mut delay_line = DelayLine::new(taps / 2 + LINEAR_PHASE_BLOCK_SIZE);
+                    delay_line.set_delay(taps / 2 + LINEAR_PHASE_BLOCK_SIZE);
+
+                    LinearPhaseChannel {
+                        input: vec![0.0; LINEAR_PHASE_BLOCK_SIZE * 2],
+                        spectra: vec![spectrum.clone(); partitions],
+                        lowpassed: std::array::from_fn(|_| vec![0.0; LINEAR_PHASE_BLOCK_SIZE]),
+                        delay_line,
+                    }
+                })
+                .collect(),
+            pos: 0,
+            newest: 0,
+            forward,
+            inverse,
+            kernel: vec![0.0; taps],
+            time: vec![0.0; LINEAR_PHASE_BLOCK_SIZE * 2],
+            spectrum,
+            scratch: vec![Complex::default(); scratch_len],
+        };
+        for crossover in 0..MAX_BANDS - 1 {
+            linear_phase.update_kernel(crossover);
+        }
+
+        linear_phase
+    }
+
+    /// Clear the histories and move the kernels to their new cutoffs right away, since there's
+    /// nothing to crossfade.
+    fn reset(&mut self) {
+        for crossover in 0..MAX_BANDS - 1 {
+            if self.kernel_cutoffs[crossover] != self.cutoffs[crossover] {
+                self.update_kernel(crossover);
+            }
+        }
+        for channel in &mut self.channels {
+            channel.input.fill(0.0);
+            for spectrum in &mut channel.spectra {
+                spectrum.fill(Complex::default());
+            }
+            for lowpassed in &mut channel.lowpassed {
+                lowpassed.fill(0.0);
+            }
+            channel.delay_line.reset();
+        }
+        self.pos = 0;
+        self.newest = 0;
+    }
+
+    /// The kernel's delay plus the block that's buffered before it's convolved.
+    fn latency(&self) -> u32 {
+        (self.taps / 2 + LINEAR_PHASE_BLOCK_SIZE) as u32
+    }
+
+    /// Move the lowpass for `crossover` to `cutoff` times the sample rate. This takes effect at the
+    /// start of the next block.
+    fn set_cutoff(&mut self, crossover: usize, cutoff: f32) {
+        self.cutoffs[crossover] = cutoff;
+    }
+
+    /// Compute the partitions of the lowpass kernel for `crossover` at its current cutoff.
+    fn update_kernel(&mut self, crossover: usize) {
+        self.kernel_cutoffs[crossover] = self.cutoffs[crossover];
+        windowed_sinc(&mut self.kernel, self.cutoffs[crossover]);
+
+        // The inverse transform scales its output up by its length
+        let scale = 1.0 / (LINEAR_PHASE_BLOCK_SIZE * 2) as f32;
+        for (partition, taps) in self.partitions[crossover]
+            .iter_mut()
+            .zip(self.kernel.chunks(LINEAR_PHASE_BLOCK_SIZE))
+        {
+            self.time.fill(0.0);
+            for (sample, tap) in self.time.iter_mut().zip(taps) {
+                *sample = tap * scale;
+            }
+
+            let result =
+                self.forward
+                    .process_with_scratch(&mut self.time, partition, &mut self.scratch);
+            nih_debug_assert!(result.is_ok());
+        }
+    }
+
+    /// Convolve the block of input that was just completed with the first `crossovers` kernels.
+    fn process_block(&mut self, crossovers: usize) {
+        let blocks = self.partitions[0].len();
+        self.newest = (self.newest + 1) % blocks;
+
+        // The old kernels are kept around to fade out during this block
+        let mut moved = [false; MAX_BANDS - 1];
+        for (crossover, moved) in moved.iter_mut().enumerate().take(crossovers) {
+            if self.kernel_cutoffs[crossover] != self.cutoffs[crossover] {
+                std::mem::swap(
+                    &mut self.partitions[crossover],
+                    &mut self.previous_partitions[crossover],
+                );
+                self.update_kernel(crossover);
+                *moved = true;
+            }
+        }
+
+        for channel in &mut self.channels {
+            self.time.copy_from_slice(&channel.input);
+            let result = self.forward.process_with_scratch(
+                &mut self.time,
+                &mut channel.spectra[self.newest],
+                &mut self.scratch,
+            );
+            nih_debug_assert!(result.is_ok());
+            channel.input.copy_within(LINEAR_PHASE_BLOCK_SIZE.., 0);
+
+            // Fading in blends the result into `lowpassed` rather than overwriting it
+            let mut convolve =
+                |partitions: &[Vec<Complex<f32>>], lowpassed: &mut [f32], fade_in| {
+                    // The n-th partition lines up with the input from n blocks ago
+                    self.spectrum.fill(Complex::default());
+                    for (age, partition) in partitions.iter().enumerate() {
+                        let spectrum = &channel.spectra[(self.newest + blocks - age) % blocks];
+                        for ((bin, x), h) in self.spectrum.iter_mut().zip(spectrum).zip(partition) {
+                            *bin += *x * *h;
+                        }
+                    }
+                    // These bins are real for real signals, but the inverse transform rejects any
+                    // rounding errors there
+                    self.spectrum[0].im = 0.0;
+                    self.spectrum[LINEAR_PHASE_BLOCK_SIZE].im = 0.0;
+
+                    let result = self.inverse.process_with_scratch(
+                        &mut self.spectrum,
+                        &mut self.time,
+                        &mut self.scratch,
+                    );
+                    nih_debug_assert!(result.is_ok());
+
+                    // The first half wrapped around the previous block, only the second half is
+                    // valid
+                    let output = &self.time[LINEAR_PHASE_BLOCK_SIZE..];
+                    if fade_in {
+                        for (i, (sample, output)) in lowpassed.iter_mut().zip(output).enumerate() {
+                            let mix = (i + 1) as f32 / LINEAR_PHASE_BLOCK_SIZE as f32;
+                            *sample += (output - *sample) * mix;
+                        }
+                    } else {
+                        lowpassed.copy_from_slice(output);
+                    }
+                };
+
+            for (crossover, lowpassed) in channel.lowpassed.iter_mut().enumerate().take(crossovers)
+            {
+                if moved[crossover] {
+                    convolve(&self.previous_partitions[crossover], lowpassed, false);
+                }
+                convolve(&self.partitions[crossover], lowpassed, moved[crossover]);
+            }
+        }
+    }
+}
+
+/// Fill `kernel` with a Blackman-windowed sinc lowpass at `cutoff` times the sample rate,
+/// normalized to unity gain at DC.
+fn windowed_sinc(kernel: &mut [f32], cutoff: f32) {
+    let center = (kernel.len() / 2) as f32;
+    let length = (kernel.len() - 1) as f32;
+
+    for (i, tap) in kernel.iter_mut().enumerate() {
+        let t = i as f32 - center;
+        let sinc = if t == 0.0 {
+            2.0 * cutoff
+        } else {
+            (2.0 * PI * cutoff * t).sin() / (PI * t)
+        };
+        let phase = 2.0 * PI * i as f32 / length;
+        let window = 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+
+        *tap = sinc * window;
+    }
+
+    let sum = kernel.iter().sum::<f32>();
+    for tap in kernel.iter_mut() {
+        *tap /= sum;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_active_frequencies_are_sorted() {
+        let mut crossover = Crossover::new(48000.0, 1);
+
+        crossover.set_bands(2, CrossoverType::LinkwitzRiley);
+        crossover.set_frequencies([1000.0, 100.0, 50000.0]);
+        assert_eq!(crossover.frequencies(), [1000.0]);
+
+        crossover.set_bands(3, CrossoverType::LinkwitzRiley);
+        assert_eq!(crossover.frequencies(), [100.0, 1000.0]);
+
+        crossover.set_frequencies([500.0, 50000.0, 5.0]);
+        assert_eq!(crossover.frequencies(), [500.0, 21600.0]);
+
+        crossover.set_bands(4, CrossoverType::LinearPhase);
+        assert_eq!(crossover.frequencies(), [10.0, 500.0, 21600.0]);
+    }
+
+    #[test]
+    fn linear_phase_crossfades_moved_crossovers() {
+        let mut moved = Crossover::new(48000.0, 1);
+        moved.set_bands(2, CrossoverType::LinearPhase);
+        let mut old = Crossover::new(48000.0, 1);
+        old.set_bands(2, CrossoverType::LinearPhase);
+        let mut new = Crossover::new(48000.0, 1);
+        new.set_bands(2, CrossoverType::LinearPhase);
+        new.set_frequencies([2000.0, 1000.0, 5000.0]);
+        new.reset();
+
+        // The move happens in the middle of a block, so it takes effect once that block is done
+        let moved_at = LINEAR_PHASE_BLOCK_SIZE * 20 + 40;
+        let fade_start = LINEAR_PHASE_BLOCK_SIZE * 21;
+        let fade_end = fade_start + LINEAR_PHASE_BLOCK_SIZE;
+
+        let mut state = 0x1234_5678_u32;
+        for start in (0..fade_end + LINEAR_PHASE_BLOCK_SIZE).step_by(4) {
+            if start == moved_at {
+                moved.set_frequencies([2000.0, 1000.0, 5000.0]);
+            }
+
+            let mut channels = [[0.0; BLOCK_SIZE]; 1];
+            for sample in &mut channels[0][..4] {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                *sample = state as f32 / u32::MAX as f32 * 2.0 - 1.0;
+            }
+            let mut outputs = [[[[0.0; BLOCK_SIZE]; MAX_CHANNELS]; MAX_BANDS]; 3];
+            for (crossover, bands) in [&mut moved, &mut old, &mut new]
+                .into_iter()
+                .zip(outputs.iter_mut())
+            {
+                crossover.process(&channels, 4, bands);
+            }
+
+            for i in 0..4 {
+                let [moved, old, new] = outputs.map(|bands| bands[0][0][i]);
+                let mix = match start + i {
+                    i if i < fade_start => 0.0,
+                    i if i < fade_end => {
+                        (i + 1 - fade_start) as f32 / LINEAR_PHASE_BLOCK_SIZE as f32
+                    }
+                    _ => 1.0,
+                };
+                let expected = old + (new - old) * mix;
+                assert!(
+                    (moved - expected).abs() < 1e-5,
+                    "sample {}: {moved} != {expected}",
+                    start + i
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn linear_phase_matches_direct_convolution() {
+        const CHANNELS: usize = 2;
+        let sample_rate = 48000.0;
+        let mut crossover = Crossover::new(sample_rate, CHANNELS);
+        crossover.set_bands(MAX_BANDS, CrossoverType::LinearPhase);
+        crossover.set_frequencies([120.0, 1000.0, 5000.0]);
+
+        let latency = crossover.latency() as usize;
+        let taps = crossover.linear_phase.taps;
+        let kernels: Vec<Vec<f32>> = crossover
+            .frequencies()
+            .iter()
+            .map(|frequency| {
+                let mut kernel = vec![0.0; taps];
+                windowed_sinc(&mut kernel, frequency / sample_rate);
+                kernel
+            })
+            .collect();
+
+        // White noise, processed in blocks of varying length
+        let mut state = 0x1234_5678_u32;
+        let input: Vec<[f32; CHANNELS]> = (0..latency + 1000)
+            .map(|_| {
+                std::array::from_fn(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    state as f32 / u32::MAX as f32 * 2.0 - 1.0
+                })
+            })
+            .collect();
+        let mut output = vec![[[0.0; MAX_BANDS]; CHANNELS]; input.len()];
+
+        let mut start = 0;
+        for len in (1..=BLOCK_SIZE).cycle() {
+            let len = len.min(input.len() - start);
+            if len == 0 {
+                break;
+            }
+
+            let mut channels = [[0.0; BLOCK_SIZE]; CHANNELS];
+            for (i, samples) in input[start..start + len].iter().enumerate() {
+                for (channel, sample) in samples.iter().enumerate() {
+                    channels[channel][i] = *sample;
+                }
+            }
+            let mut bands = [[[0.0; BLOCK_SIZE]; MAX_CHANNELS]; MAX_BANDS];
+            crossover.process(&channels, len, &mut bands);
+            for (i, output) in output[start..start + len].iter_mut().enumerate() {
+                for (channel, output) in output.iter_mut().enumerate() {
+                    for (band, output) in output.iter_mut().enumerate() {
+                        *output = bands[band][channel][i];
+                    }
+                }
+            }
+
+            start += len;
+        }
+
+        for (i, output) in output.iter().enumerate().skip(latency) {
+            for (channel, output) in output.iter().enumerate() {
+                let delayed = |delay: usize| input.get(i - delay).map_or(0.0, |x| x[channel]);
+                let lowpassed: Vec<f32> = kernels
+                    .iter()
+                    .map(|kernel| {
+                        (0..taps)
+                            .filter(|tap| tap + LINEAR_PHASE_BLOCK_SIZE <= i)
+                            .map(|tap| kernel[tap] * delayed(tap + LINEAR_PHASE_BLOCK_SIZE))
+                            .sum()
+                    })
+                    .collect();
+
+                let mut lower = 0.0;
+                for (band, output) in output.iter().enumerate() {
+                    let upper = lowpassed.get(band).copied().unwrap_or(delayed(latency));
+                    assert!(
+                        (output - (upper - lower)).abs() < 1e-4,
+                        "band {band}, channel {channel}, sample {i}: {output} != {}",
+                        upper - lower
+                    );
+                    lower = upper;
+                }
+                assert!((output.iter().sum::<f32>() - delayed(latency)).abs() < 1e-5);
+            }
+        }
+    }
+}
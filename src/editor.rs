@@ -1,10 +1,12 @@
+mod band_curve;
 mod curve;
 mod threshold_lines;
 
 use astra::prelude::*;
+use band_curve::BandCurve;
 use curve::ClippingCurve;
 use cyma::prelude::*;
-use nih_plug::params::Param;
+use nih_plug::params::{FloatParam, Param};
 use nih_plug::prelude::{Editor, Enum};
 use nih_plug::util::db_to_gain;
 use nih_plug_vizia::vizia::{image, prelude::*};
@@ -15,7 +17,7 @@ use std::sync::{Arc, Mutex};
 use threshold_lines::ThresholdLines;
 
 use crate::preferences::{load_preferences, store_preferences, Preferences};
-use crate::KlypParams;
+use crate::{crossover::MAX_BANDS, KlypParams, StereoMode};
 
 #[derive(Enum, Default, Clone, Serialize, Deserialize)]
 pub enum RangePreset {
@@ -99,6 +101,52 @@ pub enum EditorEvent {
     UpdateDuration(usize),
 }
 
+/// The ticks for the mid and side threshold offsets.
+fn offset_ticks() -> impl Iterator<Item = SliderTick> {
+    (0..=8).map(|i| {
+        let pos = i as f32 / 8.0;
+        let value = -24 + (pos * 48.0) as i16;
+        let short = value % 12 != 0;
+
+        SliderTick {
+            pos,
+            label: (!short).then(|| format!("{:}", value)),
+            short,
+        }
+    })
+}
+
+/// The ticks for a frequency parameter, leaving out the frequencies outside of its range.
+fn frequency_ticks(param: &FloatParam) -> impl Iterator<Item = SliderTick> + '_ {
+    [
+        (20.0, false),
+        (50.0, false),
+        (100.0, true),
+        (200.0, false),
+        (500.0, false),
+        (1000.0, true),
+        (2000.0, false),
+        (5000.0, false),
+        (10000.0, true),
+        (20000.0, false),
+    ]
+    .into_iter()
+    .filter(|(frequency, _)| {
+        (param.preview_plain(0.0)..=param.preview_plain(1.0)).contains(frequency)
+    })
+    .map(|(frequency, text)| SliderTick {
+        pos: param.preview_normalized(frequency),
+        label: text.then(|| {
+            if frequency >= 1000.0 {
+                format!("{:.0}k", frequency / 1000.0)
+            } else {
+                format!("{:.0}", frequency)
+            }
+        }),
+        short: !text,
+    })
+}
+
 pub(crate) fn default_state() -> Arc<ViziaState> {
     ViziaState::new(|| (640, 400))
 }
@@ -193,27 +241,319 @@ pub(crate) fn create(
                                 })
                                 .height(Auto)
                                 .col_between(Stretch(1.0));
+                                HStack::new(cx, |cx| {
+                                    Label::new(cx, "FILTER QUALITY")
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                    ParamSelector::new(cx, Data::params, |p| {
+                                        &p.antialiasing.quality
+                                    })
+                                    .top(Stretch(1.0))
+                                    .bottom(Stretch(1.0));
+                                })
+                                .height(Auto)
+                                .col_between(Stretch(1.0));
+                                HStack::new(cx, |cx| {
+                                    Label::new(cx, "FILTER PHASE")
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                    ParamSelector::new(cx, Data::params, |p| &p.antialiasing.phase)
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                })
+                                .height(Auto)
+                                .col_between(Stretch(1.0));
+                                HStack::new(cx, |cx| {
+                                    Label::new(cx, "ANTIDERIVATIVE")
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                    ParamSelector::new(cx, Data::params, |p| {
+                                        &p.antialiasing.antiderivative
+                                    })
+                                    .top(Stretch(1.0))
+                                    .bottom(Stretch(1.0));
+                                })
+                                .height(Auto)
+                                .col_between(Stretch(1.0));
+                                HStack::new(cx, |cx| {
+                                    Label::new(cx, "RENDER OVERSAMPLING")
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                    ParamSelector::new(cx, Data::params, |p| {
+                                        &p.antialiasing.render_oversampling
+                                    })
+                                    .top(Stretch(1.0))
+                                    .bottom(Stretch(1.0));
+                                })
+                                .height(Auto)
+                                .col_between(Stretch(1.0));
+                                HStack::new(cx, |cx| {
+                                    Label::new(cx, "RENDER ANTIDERIVATIVE")
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                    ParamSelector::new(cx, Data::params, |p| {
+                                        &p.antialiasing.render_antiderivative
+                                    })
+                                    .top(Stretch(1.0))
+                                    .bottom(Stretch(1.0));
+                                })
+                                .height(Auto)
+                                .col_between(Stretch(1.0));
+                                HStack::new(cx, |cx| {
+                                    Label::new(cx, "CONSTANT LATENCY")
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                    ParamSwitch::new(cx, Data::params, |p| {
+                                        &p.antialiasing.constant_latency
+                                    })
+                                    .top(Stretch(1.0))
+                                    .bottom(Stretch(1.0));
+                                })
+                                .height(Auto)
+                                .col_between(Stretch(1.0));
+                            })
+                            .child_top(Pixels(4.0))
+                            .child_right(Pixels(4.0))
+                            .child_bottom(Pixels(4.0))
+                            .child_left(Pixels(6.0))
+                            .row_between(Pixels(2.0))
+                            .height(Auto);
+                        },
+                    )
+                    .left(Stretch(1.0))
+                    .top(Stretch(1.0));
+                    let crossover_params = params.clone();
+                    let params = params.clone();
+                    Dropdown::new(
+                        cx,
+                        |cx| {
+                            HStack::new(cx, |cx| {
                                 ParamWidgetBase::view(
                                     cx,
                                     Data::params,
-                                    |p| &p.antialiasing.oversampling,
-                                    |cx, o| {
-                                        HStack::new(cx, |cx| {
-                                            Label::new(cx, "ANTIDERIVATIVE")
-                                                .top(Stretch(1.0))
-                                                .bottom(Stretch(1.0));
-                                            ParamSwitch::new(cx, Data::params, |p| {
-                                                &p.antialiasing.antiderivative
-                                            })
-                                            .top(Stretch(1.0))
-                                            .bottom(Stretch(1.0));
+                                    |p| &p.shape,
+                                    |cx, s| {
+                                        Label::new(
+                                            cx,
+                                            s.make_lens(|s| {
+                                                s.normalized_value_to_string(
+                                                    s.modulated_normalized_value(),
+                                                    true,
+                                                )
+                                            }),
+                                        )
+                                        .width(Stretch(1.0))
+                                        .pointer_events(false);
+                                    },
+                                );
+                                Image::new(cx, "chevron_down.png")
+                                    .pointer_events(false)
+                                    .width(Pixels(8.0))
+                                    .height(Pixels(6.0));
+                            })
+                        },
+                        move |cx| {
+                            VStack::new(cx, |cx| {
+                                HStack::new(cx, |cx| {
+                                    Label::new(cx, "SHAPE")
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                    ParamSelector::new(cx, Data::params, |p| &p.shape)
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                })
+                                .height(Auto)
+                                .col_between(Stretch(1.0));
+                                HStack::new(cx, |cx| {
+                                    Label::new(cx, "LINK +/-")
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                    ParamSwitch::new(cx, Data::params, |p| &p.link)
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                })
+                                .height(Auto)
+                                .col_between(Stretch(1.0));
+                                ParamWidgetBase::view(
+                                    cx,
+                                    Data::params,
+                                    |p| &p.link,
+                                    |cx, link| {
+                                        VStack::new(cx, |cx| {
+                                            ParamSlider::new(
+                                                cx,
+                                                Data::params,
+                                                |p| &p.negative_threshold,
+                                                [0.0, -6.0, -12.0, -18.0, -24.0, -36.0, -100.0]
+                                                    .iter()
+                                                    .map(|x| SliderTick {
+                                                        pos: params
+                                                            .negative_threshold
+                                                            .preview_normalized(db_to_gain(*x)),
+                                                        label: Some(format!("{:.0}", x)),
+                                                        short: false,
+                                                    }),
+                                            );
+                                            ParamSlider::new(
+                                                cx,
+                                                Data::params,
+                                                |p| &p.negative_softness,
+                                                (0..=4).map(|x| {
+                                                    let pos = x as f32 / 4.0;
+
+                                                    SliderTick {
+                                                        pos,
+                                                        label: Some(format!("{:.0}", pos * 100.0)),
+                                                        short: false,
+                                                    }
+                                                }),
+                                            );
+                                        })
+                                        .toggle_class(
+                                            "disabled",
+                                            link.make_lens(|l| l.unmodulated_plain_value()),
+                                        )
+                                        .row_between(Pixels(8.0))
+                                        .height(Auto);
+                                    },
+                                );
+                                HStack::new(cx, |cx| {
+                                    Label::new(cx, "DC FILTER")
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                    ParamSelector::new(cx, Data::params, |p| &p.dc_filter)
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                })
+                                .height(Auto)
+                                .col_between(Stretch(1.0));
+                                ParamSlider::new(
+                                    cx,
+                                    Data::params,
+                                    |p| &p.bias,
+                                    (0..=8).map(|x| {
+                                        let pos = x as f32 / 8.0;
+                                        let short = x % 2 != 0;
+
+                                        SliderTick {
+                                            pos,
+                                            label: (!short)
+                                                .then_some(format!("{:.1}", pos * 2.0 - 1.0)),
+                                            short,
+                                        }
+                                    }),
+                                );
+                                ParamSlider::new(
+                                    cx,
+                                    Data::params,
+                                    |p| &p.emphasis,
+                                    (0..=4).map(|x| {
+                                        let pos = x as f32 / 4.0;
+
+                                        SliderTick {
+                                            pos,
+                                            label: Some(format!("{:.0}", pos * 24.0 - 12.0)),
+                                            short: false,
+                                        }
+                                    }),
+                                );
+                                ParamSlider::new(
+                                    cx,
+                                    Data::params,
+                                    |p| &p.emphasis_frequency,
+                                    frequency_ticks(&params.emphasis_frequency),
+                                );
+                                HStack::new(cx, |cx| {
+                                    Label::new(cx, "GAIN COMP.")
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                    ParamSelector::new(cx, Data::params, |p| &p.gain_compensation)
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                })
+                                .height(Auto)
+                                .col_between(Stretch(1.0));
+                                HStack::new(cx, |cx| {
+                                    Label::new(cx, "DELTA")
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                    ParamSwitch::new(cx, Data::params, |p| &p.delta)
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                })
+                                .height(Auto)
+                                .col_between(Stretch(1.0));
+                                ParamSlider::new(
+                                    cx,
+                                    Data::params,
+                                    |p| &p.stereo_link,
+                                    (0..=4).map(|x| {
+                                        let pos = x as f32 / 4.0;
+
+                                        SliderTick {
+                                            pos,
+                                            label: Some(format!("{:.0}", pos * 100.0)),
+                                            short: false,
+                                        }
+                                    }),
+                                );
+                                HStack::new(cx, |cx| {
+                                    Label::new(cx, "LINK GROUP")
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                    ParamSelector::new(cx, Data::params, |p| &p.link_group)
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                })
+                                .height(Auto)
+                                .col_between(Stretch(1.0));
+                                HStack::new(cx, |cx| {
+                                    Label::new(cx, "CLIP LFE")
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                    ParamSwitch::new(cx, Data::params, |p| &p.clip_lfe)
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                })
+                                .height(Auto)
+                                .col_between(Stretch(1.0));
+                                HStack::new(cx, |cx| {
+                                    Label::new(cx, "STEREO")
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                    ParamSelector::new(cx, Data::params, |p| &p.stereo_mode)
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                })
+                                .height(Auto)
+                                .col_between(Stretch(1.0));
+                                ParamWidgetBase::view(
+                                    cx,
+                                    Data::params,
+                                    |p| &p.stereo_mode,
+                                    |cx, stereo_mode| {
+                                        VStack::new(cx, |cx| {
+                                            ParamSlider::new(
+                                                cx,
+                                                Data::params,
+                                                |p| &p.mid_threshold_offset,
+                                                offset_ticks(),
+                                            );
+                                            ParamSlider::new(
+                                                cx,
+                                                Data::params,
+                                                |p| &p.side_threshold_offset,
+                                                offset_ticks(),
+                                            );
                                         })
                                         .toggle_class(
                                             "disabled",
-                                            o.make_lens(|o| o.unmodulated_plain_value() == 0),
+                                            stereo_mode.make_lens(|m| {
+                                                m.unmodulated_plain_value() == StereoMode::LeftRight
+                                            }),
                                         )
-                                        .height(Auto)
-                                        .col_between(Stretch(1.0));
+                                        .row_between(Pixels(8.0))
+                                        .height(Auto);
                                     },
                                 );
                             })
@@ -225,7 +565,196 @@ pub(crate) fn create(
                             .height(Auto);
                         },
                     )
+                    .width(Pixels(64.0))
+                    .top(Stretch(1.0));
+                    Dropdown::new(
+                        cx,
+                        |cx| {
+                            HStack::new(cx, |cx| {
+                                ParamWidgetBase::view(
+                                    cx,
+                                    Data::params,
+                                    |p| &p.band_count,
+                                    |cx, b| {
+                                        Label::new(
+                                            cx,
+                                            b.make_lens(|b| {
+                                                b.normalized_value_to_string(
+                                                    b.modulated_normalized_value(),
+                                                    true,
+                                                )
+                                            }),
+                                        )
+                                        .width(Stretch(1.0))
+                                        .pointer_events(false);
+                                    },
+                                );
+                                Image::new(cx, "chevron_down.png")
+                                    .pointer_events(false)
+                                    .width(Pixels(8.0))
+                                    .height(Pixels(6.0));
+                            })
+                        },
+                        move |cx| {
+                            VStack::new(cx, |cx| {
+                                HStack::new(cx, |cx| {
+                                    Label::new(cx, "BANDS")
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                    ParamSelector::new(cx, Data::params, |p| &p.band_count)
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                })
+                                .height(Auto)
+                                .col_between(Stretch(1.0));
+                                HStack::new(cx, |cx| {
+                                    Label::new(cx, "CROSSOVER")
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                    ParamSelector::new(cx, Data::params, |p| &p.crossover)
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                })
+                                .height(Auto)
+                                .col_between(Stretch(1.0));
+                                HStack::new(cx, |cx| {
+                                    Label::new(cx, "PROTECT LOWS")
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                    ParamSwitch::new(cx, Data::params, |p| &p.protect_lows)
+                                        .top(Stretch(1.0))
+                                        .bottom(Stretch(1.0));
+                                })
+                                .height(Auto)
+                                .col_between(Stretch(1.0));
+                                ParamWidgetBase::view(
+                                    cx,
+                                    Data::params,
+                                    |p| &p.protect_lows,
+                                    |cx, protect_lows| {
+                                        ParamSlider::new(
+                                            cx,
+                                            Data::params,
+                                            |p| &p.protect_lows_frequency,
+                                            frequency_ticks(
+                                                &crossover_params.protect_lows_frequency,
+                                            ),
+                                        )
+                                        .toggle_class(
+                                            "disabled",
+                                            protect_lows
+                                                .make_lens(|p| !p.unmodulated_plain_value()),
+                                        );
+                                    },
+                                );
+                                ParamWidgetBase::view(
+                                    cx,
+                                    Data::params,
+                                    |p| &p.band_count,
+                                    |cx, band_count| {
+                                        VStack::new(cx, |cx| {
+                                            // A crossover is only used once there's a band above it
+                                            ParamSlider::new(
+                                                cx,
+                                                Data::params,
+                                                |p| &p.low_crossover,
+                                                frequency_ticks(&crossover_params.low_crossover),
+                                            )
+                                            .toggle_class(
+                                                "disabled",
+                                                band_count
+                                                    .make_lens(|b| b.unmodulated_plain_value() < 2),
+                                            );
+                                            ParamSlider::new(
+                                                cx,
+                                                Data::params,
+                                                |p| &p.mid_crossover,
+                                                frequency_ticks(&crossover_params.mid_crossover),
+                                            )
+                                            .toggle_class(
+                                                "disabled",
+                                                band_count
+                                                    .make_lens(|b| b.unmodulated_plain_value() < 3),
+                                            );
+                                            ParamSlider::new(
+                                                cx,
+                                                Data::params,
+                                                |p| &p.high_crossover,
+                                                frequency_ticks(&crossover_params.high_crossover),
+                                            )
+                                            .toggle_class(
+                                                "disabled",
+                                                band_count
+                                                    .make_lens(|b| b.unmodulated_plain_value() < 4),
+                                            );
+                                            for band in 0..MAX_BANDS {
+                                                HStack::new(cx, |cx| {
+                                                    BandCurve::new(
+                                                        cx,
+                                                        Data::params
+                                                            .map(move |p| p.band_threshold(band)),
+                                                        Data::params
+                                                            .map(move |p| p.band_curve(band)),
+                                                    )
+                                                    .size(Pixels(40.0));
+                                                    VStack::new(cx, |cx| {
+                                                        ParamSlider::new(
+                                                            cx,
+                                                            Data::params,
+                                                            move |p| {
+                                                                &p.bands[band].threshold_offset
+                                                            },
+                                                            offset_ticks(),
+                                                        );
+                                                        ParamSlider::new(
+                                                            cx,
+                                                            Data::params,
+                                                            move |p| &p.bands[band].softness_offset,
+                                                            (0..=4).map(|x| {
+                                                                let pos = x as f32 / 4.0;
+
+                                                                SliderTick {
+                                                                    pos,
+                                                                    label: Some(format!(
+                                                                        "{:.0}",
+                                                                        pos * 200.0 - 100.0
+                                                                    )),
+                                                                    short: false,
+                                                                }
+                                                            }),
+                                                        );
+                                                    })
+                                                    .row_between(Pixels(8.0))
+                                                    .height(Auto);
+                                                })
+                                                // The offsets only apply while the signal is split into bands
+                                                .toggle_class(
+                                                    "disabled",
+                                                    band_count.make_lens(move |b| {
+                                                        let bands = b.unmodulated_plain_value();
+                                                        bands == 1 || band as i32 >= bands
+                                                    }),
+                                                )
+                                                .col_between(Pixels(6.0))
+                                                .height(Auto);
+                                            }
+                                        })
+                                        .row_between(Pixels(8.0))
+                                        .height(Auto);
+                                    },
+                                );
+                            })
+                            .child_top(Pixels(4.0))
+                            .child_right(Pixels(4.0))
+                            .child_bottom(Pixels(4.0))
+                            .child_left(Pixels(6.0))
+                            .row_between(Pixels(2.0))
+                            .height(Auto);
+                        },
+                    )
+                    .width(Pixels(64.0))
                     .left(Stretch(1.0))
+                    .right(Stretch(1.0))
                     .top(Stretch(1.0));
                 })
                 .size(Pixels(212.0))
@@ -304,6 +833,36 @@ pub(crate) fn create(
                             }
                         }),
                     );
+                    ParamSlider::new(
+                        cx,
+                        Data::params,
+                        |params| &params.mix,
+                        (0..=20).map(|x| {
+                            let pos = x as f32 / 20.0;
+                            let short = x % 5 != 0;
+
+                            SliderTick {
+                                pos,
+                                label: (!short).then_some(format!("{:.0}", pos * 100.0)),
+                                short,
+                            }
+                        }),
+                    );
+                    ParamSlider::new(
+                        cx,
+                        Data::params,
+                        |p| &p.output_gain,
+                        (0..=16).map(|i| {
+                            let pos = i as f32 / 16.0;
+                            let value = -24 + (pos * 48.0) as i16;
+                            let short = value % 6 != 0;
+                            SliderTick {
+                                pos,
+                                label: (!short).then(|| format!("{:}", value)),
+                                short,
+                            }
+                        }),
+                    );
                 })
                 .child_space(Pixels(12.0))
                 .row_between(Pixels(8.0))
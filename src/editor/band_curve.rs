@@ -0,0 +1,92 @@
+use crate::antialiasing::Curve;
+use crate::transfer;
+
+use nih_plug_vizia::vizia::{prelude::*, vg};
+
+/// A small view of one band's clipping curve. Unlike the main clipping curve, this doesn't show the
+/// input level and can't be dragged, and only the positive half is drawn.
+pub struct BandCurve<T, C>
+where
+    T: Lens<Target = f32>,
+    C: Lens<Target = Curve>,
+{
+    threshold: T,
+    curve: C,
+}
+
+impl<T, C> BandCurve<T, C>
+where
+    T: Lens<Target = f32>,
+    C: Lens<Target = Curve>,
+{
+    pub fn new(cx: &mut Context, threshold: T, curve: C) -> Handle<Self> {
+        Self { threshold, curve }.build(cx, |_| {})
+    }
+}
+
+impl<T, C> View for BandCurve<T, C>
+where
+    T: Lens<Target = f32>,
+    C: Lens<Target = Curve>,
+{
+    fn element(&self) -> Option<&'static str> {
+        Some("band-curve")
+    }
+
+    fn draw(&self, cx: &mut DrawContext, canvas: &mut Canvas) {
+        let threshold = self.threshold.get(cx);
+        let curve = self.curve.get(cx);
+
+        let bounds = cx.bounds();
+
+        let x = bounds.x;
+        let y = bounds.y;
+        let w = bounds.w;
+        let h = bounds.h;
+
+        let line_width = cx.scale_factor();
+
+        // Background
+        canvas.fill_path(
+            &{
+                let mut path = vg::Path::new();
+                path.move_to(x, y);
+                path.line_to(x + w, y);
+                path.line_to(x + w, y + h);
+                path.line_to(x, y + h);
+                path.close();
+                path
+            },
+            &vg::Paint::color(vg::Color::rgb(219, 221, 229)),
+        );
+
+        // Threshold, which stays at the edge of the view when it's above 0 dB
+        let top = y + (1.0 - threshold).clamp(0.0, 1.0) * h;
+        canvas.stroke_path(
+            &{
+                let mut path = vg::Path::new();
+                path.move_to(x, top);
+                path.line_to(x + w, top);
+                path
+            },
+            &vg::Paint::color(vg::Color::rgb(208, 10, 10)).with_line_width(line_width),
+        );
+
+        // Clipping curve
+        canvas.stroke_path(
+            &{
+                let mut path = vg::Path::new();
+                path.move_to(x, y + h);
+                (0..=w.ceil() as u32).for_each(|i| {
+                    let sample = i as f32 / w;
+                    path.line_to(
+                        x + i as f32,
+                        y + h * (1.0 - transfer(sample, threshold, &curve)),
+                    );
+                });
+                path
+            },
+            &vg::Paint::color(vg::Color::rgb(0, 0, 0)).with_line_width(line_width),
+        );
+    }
+}
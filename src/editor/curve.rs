@@ -2,6 +2,7 @@ use core::slice;
 use std::sync::atomic::AtomicBool;
 use std::sync::{Arc, Mutex};
 
+use crate::antialiasing::Curve;
 use crate::editor::RangePreset;
 use crate::transfer;
 
@@ -182,24 +183,17 @@ impl<R: Lens<Target = RangePreset>> ClippingCurve<R> {
                         Data::params,
                         |params| &params.threshold,
                         |cx, threshold| {
-                            ParamWidgetBase::view(
-                                cx,
-                                Data::params,
-                                |params| &params.softness,
-                                |cx, softness| {
-                                    InnerCurve {
-                                        gain: gain.make_lens(|p| p.value()),
-                                        threshold: threshold.make_lens(|p| p.value()),
-                                        softness: softness.make_lens(|p| p.value()),
-                                        range,
-                                        bold: bold.clone(),
-                                        bus,
-                                        accumulator,
-                                        dispatcher_handle,
-                                    }
-                                    .build(cx, |_| {});
-                                },
-                            )
+                            InnerCurve {
+                                gain: gain.make_lens(|p| p.value()),
+                                threshold: threshold.make_lens(|p| p.value()),
+                                curve: Data::params.map(|params| params.curve()),
+                                range,
+                                bold: bold.clone(),
+                                bus,
+                                accumulator,
+                                dispatcher_handle,
+                            }
+                            .build(cx, |_| {});
                         },
                     )
                 },
@@ -208,17 +202,17 @@ impl<R: Lens<Target = RangePreset>> ClippingCurve<R> {
     }
 }
 
-struct InnerCurve<G, T, S, R, A>
+struct InnerCurve<G, T, C, R, A>
 where
     G: Lens<Target = f32>,
     T: Lens<Target = f32>,
-    S: Lens<Target = f32>,
+    C: Lens<Target = Curve>,
     R: Lens<Target = RangePreset>,
     A: Accumulator + 'static,
 {
     gain: G,
     threshold: T,
-    softness: S,
+    curve: C,
     range: R,
     bold: Arc<AtomicBool>,
     bus: Arc<MonoBus>,
@@ -226,11 +220,11 @@ where
     dispatcher_handle: Arc<dyn Fn(slice::Iter<f32>)>,
 }
 
-impl<G, T, S, R, A> View for InnerCurve<G, T, S, R, A>
+impl<G, T, C, R, A> View for InnerCurve<G, T, C, R, A>
 where
     G: Lens<Target = f32>,
     T: Lens<Target = f32>,
-    S: Lens<Target = f32>,
+    C: Lens<Target = Curve>,
     R: Lens<Target = RangePreset>,
     A: Accumulator + 'static,
 {
@@ -238,7 +232,7 @@ where
         self.bus.update();
 
         let threshold = self.threshold.get(cx);
-        let softness = self.softness.get(cx);
+        let curve = self.curve.get(cx);
         let range = self.range.get(cx);
 
         let size = range.raw_scalar();
@@ -288,72 +282,94 @@ where
         let h = h / size;
         let w_scaled = w / size;
 
-        // Clipping Curve
+        // Clipping Curve. The negative half is drawn as a magnitude on top of the positive half, but
+        // only if the two halves actually differ
 
         let in_peak = self.accumulator.lock().unwrap().prev();
         let limit = ((w_scaled.ceil() + padding_scaled) * in_peak) as u32;
+        let end = (w_scaled.ceil() + padding_scaled) as u32;
 
-        let mut clipping_curve = vg::Path::new();
+        let polarities: &[f32] = if curve.is_symmetric() {
+            &[1.0]
+        } else {
+            &[1.0, -1.0]
+        };
 
-        clipping_curve.move_to(x, y + h + offset);
+        for polarity in polarities {
+            let point = |i: u32| {
+                let sample = polarity * i as f32 / w_scaled;
+                y + h * (1.0 - polarity * transfer(sample, threshold, &curve)) + offset
+            };
 
-        (0..limit).for_each(|i| {
-            clipping_curve.line_to(
-                x + i as f32,
-                y + h * (1.0 - transfer(i as f32 / w_scaled, threshold, softness)) + offset,
-            )
-        });
-
-        let filled_portion = clipping_curve.clone();
-        (limit..(w_scaled.ceil() + padding_scaled) as u32).for_each(|i| {
-            clipping_curve.line_to(
-                x + i as f32,
-                y + h * (1.0 - transfer(i as f32 / w_scaled, threshold, softness)) + offset,
-            )
-        });
+            let mut clipping_curve = vg::Path::new();
 
-        canvas.stroke_path(
-            &clipping_curve,
-            &vg::Paint::color(vg::Color::rgb(192, 195, 204)).with_line_width(line_width),
-        );
-        canvas.stroke_path(
-            &filled_portion,
-            &vg::Paint::color(vg::Color::rgb(0, 0, 0)).with_line_width(line_width),
-        );
+            clipping_curve.move_to(x, y + h + offset);
 
-        let red = vg::Color::rgb(208, 10, 10);
+            (0..limit).for_each(|i| clipping_curve.line_to(x + i as f32, point(i)));
 
-        let top = y + (1.0 - threshold) * h + offset;
-        let bottom = y + (1.0 - threshold * (1.0 - softness)) * h + offset;
+            let filled_portion = clipping_curve.clone();
+            (limit..end).for_each(|i| clipping_curve.line_to(x + i as f32, point(i)));
 
-        let bold = self.bold.load(std::sync::atomic::Ordering::Relaxed);
-
-        canvas.stroke_path(
-            &{
-                let mut path = vg::Path::new();
+            canvas.stroke_path(
+                &clipping_curve,
+                &vg::Paint::color(vg::Color::rgb(192, 195, 204)).with_line_width(line_width),
+            );
+            canvas.stroke_path(
+                &filled_portion,
+                &vg::Paint::color(vg::Color::rgb(0, 0, 0)).with_line_width(line_width),
+            );
+        }
 
-                path.move_to(x - padding_scaled, top);
-                path.line_to(x + w + padding_scaled, top);
+        let red = vg::Color::rgb(208, 10, 10);
 
-                path
-            },
-            &vg::Paint::color(red).with_line_width(line_width * if bold { 2.0 } else { 1.0 }),
-        );
+        let bold = self.bold.load(std::sync::atomic::Ordering::Relaxed);
 
-        if bottom - top >= 1.0 {
-            canvas.fill_path(
+        let shape = curve.shape;
+        let negative_threshold = threshold * curve.negative_threshold as f32;
+        let thresholds = [
+            (
+                threshold,
+                shape.softness(curve.softness) as f32,
+                if bold { 2.0 } else { 1.0 },
+            ),
+            (
+                negative_threshold,
+                shape.softness(curve.negative_softness) as f32,
+                1.0,
+            ),
+        ];
+
+        for (threshold, knee, width) in thresholds.into_iter().take(polarities.len()) {
+            let top = y + (1.0 - threshold) * h + offset;
+            let bottom = y + (1.0 - threshold * (1.0 - knee)) * h + offset;
+
+            canvas.stroke_path(
                 &{
                     let mut path = vg::Path::new();
 
                     path.move_to(x - padding_scaled, top);
                     path.line_to(x + w + padding_scaled, top);
-                    path.line_to(x + w + padding_scaled, bottom);
-                    path.line_to(x - padding_scaled, bottom);
 
                     path
                 },
-                &vg::Paint::color(vg::Color { a: 0.25, ..red }),
+                &vg::Paint::color(red).with_line_width(line_width * width),
             );
+
+            if bottom - top >= 1.0 {
+                canvas.fill_path(
+                    &{
+                        let mut path = vg::Path::new();
+
+                        path.move_to(x - padding_scaled, top);
+                        path.line_to(x + w + padding_scaled, top);
+                        path.line_to(x + w + padding_scaled, bottom);
+                        path.line_to(x - padding_scaled, bottom);
+
+                        path
+                    },
+                    &vg::Paint::color(vg::Color { a: 0.25, ..red }),
+                );
+            }
         }
     }
 }
@@ -1,3 +1,4 @@
+use crate::antialiasing::Curve;
 use crate::editor::RangePreset;
 
 use super::Data;
@@ -133,46 +134,42 @@ impl ThresholdLines {
                 Data::params,
                 |params| &params.threshold,
                 |cx, threshold| {
-                    ParamWidgetBase::view(
-                        cx,
-                        Data::params,
-                        |params| &params.softness,
-                        |cx, softness| {
-                            Lines {
-                                threshold: threshold.make_lens(|p| p.value()),
-                                softness: softness.make_lens(|p| p.value()),
-                                range,
-                            }
-                            .build(cx, |_| {});
-                        },
-                    )
+                    Lines {
+                        threshold: threshold.make_lens(|p| p.value()),
+                        curve: Data::params.map(|params| params.curve()),
+                        range,
+                    }
+                    .build(cx, |_| {});
                 },
             );
         })
     }
 }
 
-struct Lines<T, S, R>
+struct Lines<T, C, R>
 where
     T: Lens<Target = f32>,
-    S: Lens<Target = f32>,
+    C: Lens<Target = Curve>,
     R: Lens<Target = RangePreset>,
 {
     threshold: T,
-    softness: S,
+    curve: C,
     range: R,
 }
 
-impl<T, S, R> View for Lines<T, S, R>
+impl<T, C, R> View for Lines<T, C, R>
 where
     T: Lens<Target = f32>,
-    S: Lens<Target = f32>,
+    C: Lens<Target = Curve>,
     R: Lens<Target = RangePreset>,
 {
     fn draw(&self, cx: &mut DrawContext, canvas: &mut Canvas) {
         let size = self.range.get(cx).raw_scalar();
+        let curve = self.curve.get(cx);
         let threshold = self.threshold.get(cx) / size;
-        let softness = self.softness.get(cx);
+        let negative_threshold = threshold * curve.negative_threshold as f32;
+        let softness = curve.shape.softness(curve.softness) as f32;
+        let negative_softness = curve.shape.softness(curve.negative_softness) as f32;
 
         let bounds = cx.bounds();
 
@@ -187,7 +184,7 @@ where
         let color_25 = vg::Color { a: 0.25, ..color };
 
         let top_a = y + (1.0 - threshold) * h / 2.0;
-        let top_b = y + (1.0 + threshold) * h / 2.0;
+        let top_b = y + (1.0 + negative_threshold) * h / 2.0;
 
         canvas.stroke_path(
             &{
@@ -214,7 +211,7 @@ where
         );
 
         let bottom_a = y + (1.0 - threshold * (1.0 - softness)) * h / 2.0;
-        let bottom_b = y + (1.0 + threshold * (1.0 - softness)) * h / 2.0;
+        let bottom_b = y + (1.0 + negative_threshold * (1.0 - negative_softness)) * h / 2.0;
 
         if bottom_a - top_a >= 1.0 {
             canvas.fill_path(
@@ -230,6 +227,9 @@ where
                 },
                 &vg::Paint::color(color_25),
             );
+        }
+
+        if top_b - bottom_b >= 1.0 {
             canvas.fill_path(
                 &{
                     let mut path = vg::Path::new();
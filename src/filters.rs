@@ -0,0 +1,363 @@
+//! Apart from [`LoudnessMeter`], these only handle a single audio channel. Use one instance per
+//! channel for multichannel audio.
+
+use std::f32::consts::PI;
+
+/// A first-order highpass filter that removes DC offsets, using the usual one-pole, one-zero
+/// structure. This is minimum-phase, so unlike a linear-phase highpass it does not add any latency
+/// that would need to be reported to the host.
+#[derive(Debug, Clone, Default)]
+pub struct DcBlocker {
+    /// The pole's radius. The closer this is to 1, the lower the cutoff frequency.
+    coefficient: f32,
+    x1: f32,
+    y1: f32,
+}
+
+impl DcBlocker {
+    /// Create a DC blocker with a cutoff frequency of roughly `cutoff` Hz.
+    pub fn new(sample_rate: f32, cutoff: f32) -> Self {
+        Self {
+            coefficient: (-2.0 * PI * cutoff / sample_rate).exp(),
+            ..Self::default()
+        }
+    }
+
+    /// Reset the filter to its initial state.
+    pub fn reset(&mut self) {
+        self.x1 = 0.0;
+        self.y1 = 0.0;
+    }
+
+    #[inline]
+    pub fn process(&mut self, x: f32) -> f32 {
+        let y = x - self.x1 + self.coefficient * self.y1;
+
+        self.x1 = x;
+        self.y1 = y;
+
+        y
+    }
+}
+
+/// A delay line with an adjustable delay, used to line signals up with the clipped signal.
+#[derive(Debug, Clone, Default)]
+pub struct DelayLine {
+    /// Room for the maximum delay plus the current sample.
+    buffer: Vec<f32>,
+    /// The next sample's write position.
+    pos: usize,
+    delay: usize,
+}
+
+impl DelayLine {
+    /// Create a delay line that can delay the signal by up to `max_delay` samples. The delay starts
+    /// out at zero.
+    pub fn new(max_delay: usize) -> Self {
+        Self {
+            buffer: vec![0.0; max_delay + 1],
+            ..Self::default()
+        }
+    }
+
+    /// Reset the delay line to its initial state, keeping the delay.
+    pub fn reset(&mut self) {
+        self.buffer.fill(0.0);
+        self.pos = 0;
+    }
+
+    /// Change the delay. Since the delay line keeps recording the input regardless of the delay,
+    /// this immediately outputs the input from `delay` samples ago.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `delay` exceeds the maximum delay.
+    pub fn set_delay(&mut self, delay: usize) {
+        assert!(delay < self.buffer.len());
+
+        self.delay = delay;
+    }
+
+    #[inline]
+    pub fn process(&mut self, x: f32) -> f32 {
+        self.buffer[self.pos] = x;
+
+        let read_pos = (self.pos + self.buffer.len() - self.delay) % self.buffer.len();
+        self.pos = (self.pos + 1) % self.buffer.len();
+
+        self.buffer[read_pos]
+    }
+
+    /// The input from `delay` samples before the most recently processed one, regardless of the
+    /// current delay. This lets the output be crossfaded from one delay to another.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `delay` exceeds the maximum delay.
+    #[inline]
+    pub fn read(&self, delay: usize) -> f32 {
+        assert!(delay < self.buffer.len());
+
+        let read_pos = (self.pos + 2 * self.buffer.len() - 1 - delay) % self.buffer.len();
+
+        self.buffer[read_pos]
+    }
+}
+
+/// A biquad filter in transposed direct form II, with the coefficients normalized so `a0` is 1.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    s1: f32,
+    s2: f32,
+}
+
+impl Biquad {
+    /// A second-order Butterworth lowpass, using the bilinear transform from the RBJ cookbook.
+    pub fn lowpass(sample_rate: f32, frequency: f32) -> Self {
+        let (cos, alpha) = Self::butterworth(sample_rate, frequency);
+        let a0 = 1.0 + alpha;
+
+        Self {
+            b0: (1.0 - cos) / 2.0 / a0,
+            b1: (1.0 - cos) / a0,
+            b2: (1.0 - cos) / 2.0 / a0,
+            a1: -2.0 * cos / a0,
+            a2: (1.0 - alpha) / a0,
+            ..Self::default()
+        }
+    }
+
+    /// A second-order Butterworth highpass, see [`lowpass()`][Self::lowpass()].
+    pub fn highpass(sample_rate: f32, frequency: f32) -> Self {
+        let (cos, alpha) = Self::butterworth(sample_rate, frequency);
+        let a0 = 1.0 + alpha;
+
+        Self {
+            b0: (1.0 + cos) / 2.0 / a0,
+            b1: -(1.0 + cos) / a0,
+            b2: (1.0 + cos) / 2.0 / a0,
+            a1: -2.0 * cos / a0,
+            a2: (1.0 - alpha) / a0,
+            ..Self::default()
+        }
+    }
+
+    /// A second-order allpass with the same Q as the Butterworth filters. Its phase response
+    /// matches the sum of a fourth-order Linkwitz-Riley crossover's lowpass and highpass outputs.
+    pub fn allpass(sample_rate: f32, frequency: f32) -> Self {
+        let (cos, alpha) = Self::butterworth(sample_rate, frequency);
+        let a0 = 1.0 + alpha;
+
+        Self {
+            b0: (1.0 - alpha) / a0,
+            b1: -2.0 * cos / a0,
+            b2: 1.0,
+            a1: -2.0 * cos / a0,
+            a2: (1.0 - alpha) / a0,
+            ..Self::default()
+        }
+    }
+
+    /// A tilt around `frequency` that boosts the highs by half of `gain_db` and cuts the lows by
+    /// the other half, leaving `frequency` itself untouched. This is the RBJ cookbook's high shelf
+    /// with a slope of 1, scaled down by half of its gain.
+    pub fn tilt(sample_rate: f32, frequency: f32, gain_db: f32) -> Self {
+        let (cos, alpha) = Self::butterworth(sample_rate, frequency);
+        let a = 10.0f32.powf(gain_db / 40.0);
+        let sqrt_a_alpha = 2.0 * a.sqrt() * alpha;
+        let a0 = (a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha;
+
+        Self {
+            b0: ((a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha) / a0,
+            b1: -2.0 * ((a - 1.0) + (a + 1.0) * cos) / a0,
+            b2: ((a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha) / a0,
+            a1: 2.0 * ((a - 1.0) - (a + 1.0) * cos) / a0,
+            a2: ((a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha) / a0,
+            ..Self::default()
+        }
+    }
+
+    /// The filter that exactly undoes this one, by swapping the numerator and the denominator.
+    /// This is only stable if the filter is minimum-phase, like [`tilt()`][Self::tilt()].
+    pub fn inverse(&self) -> Self {
+        Self {
+            b0: 1.0 / self.b0,
+            b1: self.a1 / self.b0,
+            b2: self.a2 / self.b0,
+            a1: self.b1 / self.b0,
+            a2: self.b2 / self.b0,
+            ..Self::default()
+        }
+    }
+
+    /// The cosine of the normalized frequency and the bandwidth term for a Q of `1 / sqrt(2)`.
+    fn butterworth(sample_rate: f32, frequency: f32) -> (f32, f32) {
+        let omega = 2.0 * PI * frequency / sample_rate;
+
+        (omega.cos(), omega.sin() / std::f32::consts::SQRT_2)
+    }
+
+    /// Switch to `other`'s coefficients while keeping this filter's state, so the frequency can
+    /// change without the filter having to settle again.
+    pub fn set_coefficients(&mut self, other: &Biquad) {
+        *self = Self {
+            s1: self.s1,
+            s2: self.s2,
+            ..*other
+        };
+    }
+
+    pub fn reset(&mut self) {
+        self.s1 = 0.0;
+        self.s2 = 0.0;
+    }
+
+    #[inline]
+    pub fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.s1;
+
+        self.s1 = self.b1 * x - self.a1 * y + self.s2;
+        self.s2 = self.b2 * x - self.a2 * y;
+
+        y
+    }
+}
+
+/// Matched pre- and de-emphasis filters around the clipping, which tilt the signal so the clipping
+/// bites more on the highs or on the lows. The de-emphasis filter is the exact inverse of the
+/// pre-emphasis filter, so the two null as long as nothing clips.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Emphasis {
+    pub pre: Biquad,
+    pub de: Biquad,
+}
+
+impl Emphasis {
+    /// Tilt the signal by `gain_db` around `frequency` before clipping, see [`Biquad::tilt()`].
+    pub fn new(sample_rate: f32, frequency: f32, gain_db: f32) -> Self {
+        let pre = Biquad::tilt(sample_rate, frequency, gain_db);
+
+        Self {
+            pre,
+            de: pre.inverse(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.pre.reset();
+        self.de.reset();
+    }
+}
+
+/// The K-weighting filter from ITU-R BS.1770, which LUFS are measured with. It consists of a high
+/// shelf that models the head's acoustics, followed by a highpass. The coefficients are computed
+/// from the analog prototypes, so this works at any sample rate.
+#[derive(Debug, Clone, Default)]
+pub struct KWeighting {
+    shelf: Biquad,
+    highpass: Biquad,
+}
+
+impl KWeighting {
+    pub fn new(sample_rate: f32) -> Self {
+        // These are the parameters the standard's 48 kHz coefficients were derived from
+        let k = (PI * 1681.9745 / sample_rate).tan();
+        let q = 0.70717525;
+        let vh = 10.0f32.powf(3.9998438 / 20.0);
+        let vb = vh.powf(0.49966677);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad {
+            b0: (vh + vb * k / q + k * k) / a0,
+            b1: 2.0 * (k * k - vh) / a0,
+            b2: (vh - vb * k / q + k * k) / a0,
+            a1: 2.0 * (k * k - 1.0) / a0,
+            a2: (1.0 - k / q + k * k) / a0,
+            ..Biquad::default()
+        };
+
+        let k = (PI * 38.135_47 / sample_rate).tan();
+        let q = 0.50032704;
+        let a0 = 1.0 + k / q + k * k;
+        let highpass = Biquad {
+            b0: 1.0,
+            b1: -2.0,
+            b2: 1.0,
+            a1: 2.0 * (k * k - 1.0) / a0,
+            a2: (1.0 - k / q + k * k) / a0,
+            ..Biquad::default()
+        };
+
+        Self { shelf, highpass }
+    }
+
+    pub fn reset(&mut self) {
+        self.shelf.reset();
+        self.highpass.reset();
+    }
+
+    #[inline]
+    pub fn process(&mut self, x: f32) -> f32 {
+        self.highpass.process(self.shelf.process(x))
+    }
+}
+
+/// Measures a signal's long-term loudness. Like LUFS, the channels are K-weighted and their powers
+/// are summed, but the power is averaged exponentially instead of over gated blocks, so the
+/// measurement can follow the signal while it plays.
+#[derive(Debug, Clone, Default)]
+pub struct LoudnessMeter {
+    k_weighting: Vec<KWeighting>,
+    sample_rate: f32,
+    /// The averaging's time constant, in seconds.
+    time: f32,
+    power: f32,
+}
+
+impl LoudnessMeter {
+    /// Create a meter for `channels` channels that averages the power over roughly `time`
+    /// seconds.
+    pub fn new(sample_rate: f32, channels: usize, time: f32) -> Self {
+        Self {
+            k_weighting: vec![KWeighting::new(sample_rate); channels],
+            sample_rate,
+            time,
+            power: 0.0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for k_weighting in &mut self.k_weighting {
+            k_weighting.reset();
+        }
+        self.power = 0.0;
+    }
+
+    /// Measure the first `len` samples of each channel in `channels`. The average is only updated
+    /// once per call, which is plenty for time constants this long.
+    pub fn process<C: AsRef<[f32]>>(&mut self, channels: &[C], len: usize) {
+        if len == 0 {
+            return;
+        }
+
+        let mut total = 0.0;
+        for (samples, k_weighting) in channels.iter().zip(self.k_weighting.iter_mut()) {
+            for sample in &samples.as_ref()[..len] {
+                let weighted = k_weighting.process(*sample);
+                total += weighted * weighted;
+            }
+        }
+
+        let coefficient = (-(len as f32) / (self.time * self.sample_rate)).exp();
+        self.power = total / len as f32 + (self.power - total / len as f32) * coefficient;
+    }
+
+    /// The averaged power of the K-weighted signal, summed over the channels.
+    pub fn power(&self) -> f32 {
+        self.power
+    }
+}
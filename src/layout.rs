@@ -0,0 +1,82 @@
+use nih_plug::prelude::{AudioIOLayout, PortNames};
+use std::num::NonZeroU32;
+
+/// The speaker layouts KLYP can process. The hosts tell them apart by their channel counts, and
+/// the channels are in the order that's common to the plugin APIs, with the left and right
+/// channels first, followed by the center and LFE channels if there are any.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelLayout {
+    Mono,
+    Stereo,
+    /// Left, right and center.
+    Lcr,
+    /// Left, right, left surround and right surround.
+    Quad,
+    Surround51,
+    Surround71,
+    /// 7.1 with four height channels.
+    Surround714,
+}
+
+/// Declares an in-place layout with the given number of channels.
+const fn io_layout(channels: u32, name: &'static str) -> AudioIOLayout {
+    AudioIOLayout {
+        main_input_channels: NonZeroU32::new(channels),
+        main_output_channels: NonZeroU32::new(channels),
+        names: PortNames {
+            layout: Some(name),
+            ..PortNames::const_default()
+        },
+        ..AudioIOLayout::const_default()
+    }
+}
+
+/// Every supported layout, with stereo as the default.
+pub const AUDIO_IO_LAYOUTS: [AudioIOLayout; 7] = [
+    io_layout(2, "Stereo"),
+    io_layout(1, "Mono"),
+    io_layout(3, "LCR"),
+    io_layout(4, "Quad"),
+    io_layout(6, "5.1"),
+    io_layout(8, "7.1"),
+    io_layout(12, "7.1.4"),
+];
+
+impl ChannelLayout {
+    pub fn from_channels(channels: usize) -> Option<Self> {
+        match channels {
+            1 => Some(Self::Mono),
+            2 => Some(Self::Stereo),
+            3 => Some(Self::Lcr),
+            4 => Some(Self::Quad),
+            6 => Some(Self::Surround51),
+            8 => Some(Self::Surround71),
+            12 => Some(Self::Surround714),
+            _ => None,
+        }
+    }
+
+    /// The index of the LFE channel, if the layout has one.
+    pub fn lfe(&self) -> Option<usize> {
+        match self {
+            Self::Surround51 | Self::Surround71 | Self::Surround714 => Some(3),
+            _ => None,
+        }
+    }
+
+    /// Whether the channel at `index` is one of the front channels, which are left, right and
+    /// center.
+    pub fn is_front(&self, index: usize) -> bool {
+        match self {
+            Self::Mono => index == 0,
+            Self::Stereo | Self::Quad => index < 2,
+            Self::Lcr | Self::Surround51 | Self::Surround71 | Self::Surround714 => index < 3,
+        }
+    }
+
+    /// Whether the channel at `index` is shown on the scopes. The LFE channel is left out, since
+    /// it would only smear the other channels' waveforms.
+    pub fn is_displayed(&self, index: usize) -> bool {
+        self.lfe() != Some(index)
+    }
+}
@@ -1,64 +1,124 @@
 mod antialiasing;
+mod chain;
+mod crossover;
 mod editor;
+mod filters;
+mod layout;
 mod oversampling;
 mod preferences;
 
-use crate::{antialiasing::Processor, preferences::Preferences};
+use crate::{
+    antialiasing::{ClipShape, Curve},
+    chain::{BlockParameters, ClipperChain},
+    crossover::{Crossover, CrossoverType, LowSplit, MAX_BANDS},
+    preferences::Preferences,
+};
 use cyma::prelude::*;
-use nih_plug::{prelude::*, util::db_to_gain_fast};
+use filters::{DcBlocker, DelayLine, Emphasis, LoudnessMeter};
+use layout::ChannelLayout;
+use nih_plug::{
+    prelude::*,
+    util::db_to_gain_fast,
+    wrapper::state::{ParamValue, PluginState},
+};
 use nih_plug_vizia::ViziaState;
-use oversampling::Lanczos3Oversampler;
-use std::{f32::consts::PI, sync::{Arc, Mutex}};
-use util::MINUS_INFINITY_GAIN;
+use oversampling::{FilterPhase, FilterQuality, Lanczos3Oversampler};
+use std::sync::{Arc, Mutex};
+use util::{db_to_gain, MINUS_INFINITY_GAIN};
 
 const BLOCK_SIZE: usize = 32;
-const MAX_OVERSAMPLING_FACTOR: usize = 3;
+const MAX_OVERSAMPLING_FACTOR: usize = 5;
 const MAX_OVERSAMPLING_TIMES: usize = 2usize.pow(MAX_OVERSAMPLING_FACTOR as u32);
 const MAX_OVERSAMPLED_BLOCK_SIZE: usize = BLOCK_SIZE * MAX_OVERSAMPLING_TIMES;
+const DC_BLOCKER_CUTOFF: f32 = 5.0;
+/// The maximum number of channels that can be clipped at once, which is enough for 7.1.4.
+const MAX_CHANNELS: usize = 12;
+/// How long switching between oversampling factors and antiderivative orders crossfades for, in
+/// seconds. Switching the DC filter on or off fades it in or out over the same time.
+const CROSSFADE_TIME: f32 = 0.02;
+/// How long the loudness matching averages the loudness over, in seconds.
+const LOUDNESS_MATCHING_TIME: f32 = 3.0;
+/// The most the loudness matching boosts or cuts the clipped signal, in dB.
+const MAX_LOUDNESS_COMPENSATION: f32 = 24.0;
+/// The loudness matching holds its gain while either signal's power is below this, which is
+/// roughly the -70 LUFS absolute gate from ITU-R BS.1770.
+const LOUDNESS_GATE: f32 = 1.0e-7;
+/// The number of samples a chain that's being switched to runs for on top of twice its latency
+/// before the crossfade starts. The minimum-phase filters need this to settle.
+const CROSSFADE_SETTLE_SAMPLES: usize = 128;
 
-#[inline]
-pub fn transfer(mut sample: f32, threshold: f32, softness: f32) -> f32 {
-    apply_transfer(&mut sample, &threshold, &softness);
-    sample
+/// Convert the left and right channels in `channels` to mid and side. Each is divided by its
+/// threshold offset, which makes the clipper clip it at the offset threshold.
+fn encode_mid_side(
+    channels: &mut [[f32; BLOCK_SIZE]],
+    len: usize,
+    mid_threshold_offset: &[f32],
+    side_threshold_offset: &[f32],
+) {
+    let [left, right] = channels else {
+        return;
+    };
+
+    for i in 0..len {
+        let mid = 0.5 * (left[i] + right[i]);
+        let side = 0.5 * (left[i] - right[i]);
+
+        left[i] = mid / db_to_gain_fast(mid_threshold_offset[i]);
+        right[i] = side / db_to_gain_fast(side_threshold_offset[i]);
+    }
 }
 
-#[inline]
-fn apply_transfer(sample: &mut f32, threshold: &f32, softness: &f32) {
-    static KNEE_OFFSET: f32 = PI / 2. - 1.;
+/// Undo `encode_mid_side()`.
+fn decode_mid_side(
+    channels: &mut [[f32; BLOCK_SIZE]],
+    len: usize,
+    mid_threshold_offset: &[f32],
+    side_threshold_offset: &[f32],
+) {
+    let [mid, side] = channels else {
+        return;
+    };
 
-    *sample /= threshold;
+    for i in 0..len {
+        let mid_sample = mid[i] * db_to_gain_fast(mid_threshold_offset[i]);
+        let side_sample = side[i] * db_to_gain_fast(side_threshold_offset[i]);
 
-    let abs_sample = sample.abs();
+        mid[i] = mid_sample + side_sample;
+        side[i] = mid_sample - side_sample;
+    }
+}
 
-    if abs_sample < 1.0 + softness * KNEE_OFFSET {
-        if abs_sample > 1.0 - softness {
-            *sample = ((1.0 - softness) + softness * (1.0 - (1.0 - abs_sample) / softness).sin())
-                * sample.signum();
+/// Add the first `len` samples of each channel in `source` to `destination`.
+fn add_channels(destination: &mut [[f32; BLOCK_SIZE]], source: &[[f32; BLOCK_SIZE]], len: usize) {
+    for (destination, source) in destination.iter_mut().zip(source.iter()) {
+        for (destination, source) in destination[..len].iter_mut().zip(source[..len].iter()) {
+            *destination += source;
         }
-    } else {
-        *sample = sample.signum();
     }
-
-    *sample *= threshold;
 }
 
 #[inline]
-fn transfer_curve(softness: f32) -> impl Fn(f32) -> f32 {
-    move |sample: f32| {
-        static KNEE_OFFSET: f32 = PI / 2. - 1.;
-
-        let abs_sample = sample.abs();
+pub fn transfer(sample: f32, threshold: f32, curve: &Curve) -> f32 {
+    curve.func((sample / threshold) as f64) as f32 * threshold
+}
 
-        if abs_sample < 1.0 + softness * KNEE_OFFSET {
-            if abs_sample > 1.0 - softness {
-                ((1.0 - softness) + softness * (1.0 - (1.0 - abs_sample) / softness).sin())
-                    * sample.signum()
-            } else {
-                sample
-            }
-        } else {
-            sample.signum()
+/// Delay `sample` with `delay_line`. While crossfading, the delay line's output at
+/// `previous_latency` is faded out by `mix` the same way as `Klyp::previous_chains`, so the delayed
+/// signal stays lined up with the clipped one.
+#[inline]
+fn delay_crossfaded(
+    delay_line: &mut DelayLine,
+    sample: f32,
+    previous_latency: Option<usize>,
+    mix: f32,
+) -> f32 {
+    let delayed = delay_line.process(sample);
+    match previous_latency {
+        Some(previous_latency) => {
+            let previous = delay_line.read(previous_latency);
+            previous + (delayed - previous) * mix
         }
+        None => delayed,
     }
 }
 
@@ -66,17 +126,198 @@ pub struct Klyp {
     params: Arc<KlypParams>,
     pre: Arc<MonoBus>,
     post: Arc<MonoBus>,
-    processors: Vec<Processor>,
-    oversamplers: Vec<Lanczos3Oversampler>,
+    /// Splits the signal into the bands that are clipped separately.
+    crossover: Crossover,
+    /// Splits off the lows that skip the clipping when `protect_lows` is enabled.
+    low_split: LowSplit,
+    /// How much of the lows `low_split` splits off, from 0 to 1. Like `dc_filter_mix`, this fades
+    /// towards the `protect_lows` setting over the crossfade time.
+    protect_lows_mix: f32,
+    /// Delays the lows by the reported latency, so they line up with the clipped highs.
+    low_delays: Vec<DelayLine>,
+    /// Clip each band with the current oversampling factor and antiderivative order. All
+    /// [`MAX_BANDS`] chains always exist, but only the ones for the current bands are used.
+    chains: Vec<ClipperChain>,
+    /// The chains that were used before the oversampling factor or antiderivative order changed.
+    /// These keep running until `chains` have been faded in.
+    previous_chains: Vec<ClipperChain>,
+    crossfade: Option<Crossfade>,
+    /// The crossfade's length in samples.
+    crossfade_length: usize,
+    sample_rate: f32,
+    dc_blockers: Vec<DcBlocker>,
+    /// How much of the DC blockers' output is used, from 0 to 1. This fades towards the DC filter
+    /// setting over the crossfade time.
+    dc_filter_mix: f32,
+    /// Delays the dry signal by the reported latency, so it lines up with the clipped signal.
+    dry_delays: Vec<DelayLine>,
+    /// Delays the smoothed gain the same way, so `GainCompensation::PreGain` undoes the gain the
+    /// clipped signal actually went through.
+    gain_delay: DelayLine,
+    /// Measures the input's loudness for `GainCompensation::Loudness`.
+    input_loudness: LoudnessMeter,
+    /// Measures the clipped signal's loudness for `GainCompensation::Loudness`.
+    output_loudness: LoudnessMeter,
+    /// The gain that makes the clipped signal as loud as the input, as a linear factor.
+    loudness_compensation: f32,
     scratch_buffers: Box<ScratchBuffers>,
-    preferences: Arc<Mutex<Option<Preferences>>>
+    preferences: Arc<Mutex<Option<Preferences>>>,
+    layout: ChannelLayout,
+    /// Whether the host is rendering offline, in which case the render settings are used. This can
+    /// only change when the plugin gets reinitialized.
+    process_mode: ProcessMode,
 }
 
-#[derive(Ord, PartialOrd, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
 pub enum Antiderivative {
     Off,
     FirstDegree,
     SecondDegree,
+    ThirdDegree,
+}
+
+impl Antiderivative {
+    /// The delay ADAA of this degree adds to the signal, in samples at the base sample rate. An
+    /// nth-degree antiderivative delays the signal by half a sample per degree at the oversampled
+    /// rate, which gets rounded to the nearest whole sample here. The old antiderivative switch
+    /// always reported one sample instead, so first-degree ADAA at 2x and second-degree ADAA at 4x
+    /// and up now report no latency at all, since they delay the signal by only a quarter sample
+    /// or less.
+    ///
+    /// The leftover fraction of up to half a sample isn't compensated, so the clipped signal and
+    /// the dry signal it gets mixed with are slightly misaligned. That comb filters the mix, but
+    /// the first notch is at the sample rate or above, so this only rolls off the highs a little:
+    /// at 48 kHz, a 50% mix with a half-sample offset is about 2 dB down at 20 kHz.
+    pub fn latency(&self, oversampling: usize) -> u32 {
+        let degree = match self {
+            Antiderivative::Off => 0,
+            Antiderivative::FirstDegree => 1,
+            Antiderivative::SecondDegree => 2,
+            Antiderivative::ThirdDegree => 3,
+        };
+
+        (degree as f32 * 0.5 / (1 << oversampling) as f32).round() as u32
+    }
+}
+
+/// The antiderivative order as it is exposed to the user.
+#[derive(Enum, Debug, PartialEq, Eq)]
+pub enum AntiderivativeOrder {
+    #[id = "off"]
+    Off,
+    #[id = "first"]
+    #[name = "1st"]
+    FirstDegree,
+    #[id = "second"]
+    #[name = "2nd"]
+    SecondDegree,
+    #[id = "third"]
+    #[name = "3rd"]
+    ThirdDegree,
+    /// Picks the order based on the oversampling factor.
+    #[id = "auto"]
+    Auto,
+}
+
+impl AntiderivativeOrder {
+    /// The antiderivative to use at the given oversampling factor.
+    pub fn resolve(&self, oversampling: usize) -> Antiderivative {
+        match self {
+            AntiderivativeOrder::Off => Antiderivative::Off,
+            AntiderivativeOrder::FirstDegree => Antiderivative::FirstDegree,
+            AntiderivativeOrder::SecondDegree => Antiderivative::SecondDegree,
+            AntiderivativeOrder::ThirdDegree => Antiderivative::ThirdDegree,
+            AntiderivativeOrder::Auto => match oversampling {
+                0 => Antiderivative::Off,
+                1 => Antiderivative::FirstDegree,
+                2.. => Antiderivative::SecondDegree,
+            },
+        }
+    }
+}
+
+/// The antiderivative order used while rendering offline.
+#[derive(Enum, Debug, PartialEq, Eq)]
+pub enum RenderAntiderivativeOrder {
+    /// Uses the regular antiderivative order.
+    #[id = "same"]
+    Same,
+    #[id = "off"]
+    Off,
+    #[id = "first"]
+    #[name = "1st"]
+    FirstDegree,
+    #[id = "second"]
+    #[name = "2nd"]
+    SecondDegree,
+    #[id = "third"]
+    #[name = "3rd"]
+    ThirdDegree,
+    #[id = "auto"]
+    Auto,
+}
+
+impl RenderAntiderivativeOrder {
+    /// The order to use while rendering offline, given the regular order.
+    pub fn resolve(&self, order: AntiderivativeOrder) -> AntiderivativeOrder {
+        match self {
+            RenderAntiderivativeOrder::Same => order,
+            RenderAntiderivativeOrder::Off => AntiderivativeOrder::Off,
+            RenderAntiderivativeOrder::FirstDegree => AntiderivativeOrder::FirstDegree,
+            RenderAntiderivativeOrder::SecondDegree => AntiderivativeOrder::SecondDegree,
+            RenderAntiderivativeOrder::ThirdDegree => AntiderivativeOrder::ThirdDegree,
+            RenderAntiderivativeOrder::Auto => AntiderivativeOrder::Auto,
+        }
+    }
+}
+
+#[derive(Enum, Debug, PartialEq, Eq)]
+pub enum DcFilter {
+    #[id = "off"]
+    Off,
+    /// Only filters the output while the clipping is asymmetric.
+    #[id = "auto"]
+    Auto,
+    #[id = "on"]
+    On,
+}
+
+#[derive(Enum, Debug, PartialEq, Eq)]
+pub enum GainCompensation {
+    #[id = "off"]
+    Off,
+    /// Applies the inverse of the pre-gain.
+    #[id = "pre_gain"]
+    #[name = "Pre-Gain"]
+    PreGain,
+    /// Matches the clipped signal's long-term loudness to the input's.
+    #[id = "loudness"]
+    Loudness,
+}
+
+#[derive(Enum, Debug, PartialEq, Eq)]
+pub enum StereoMode {
+    #[id = "left_right"]
+    #[name = "L/R"]
+    LeftRight,
+    #[id = "mid_side"]
+    #[name = "M/S"]
+    MidSide,
+    /// Only clips the mid channel.
+    #[id = "mid"]
+    Mid,
+    /// Only clips the side channel.
+    #[id = "side"]
+    Side,
+}
+
+#[derive(Enum, Debug, PartialEq, Eq)]
+pub enum LinkGroup {
+    #[id = "all"]
+    All,
+    /// Only links the left, right and center channels.
+    #[id = "front"]
+    Front,
 }
 
 #[derive(Params)]
@@ -87,6 +328,73 @@ pub struct KlypParams {
     pub threshold: FloatParam,
     #[id = "softness"]
     pub softness: FloatParam,
+    #[id = "shape"]
+    pub shape: EnumParam<ClipShape>,
+    #[id = "link"]
+    pub link: BoolParam,
+    #[id = "threshold_neg"]
+    pub negative_threshold: FloatParam,
+    #[id = "softness_neg"]
+    pub negative_softness: FloatParam,
+    #[id = "bias"]
+    pub bias: FloatParam,
+    /// Tilts the signal around `emphasis_frequency` before clipping, in dB. The tilt is undone
+    /// afterwards, so this only changes which frequencies clip first.
+    #[id = "emphasis"]
+    pub emphasis: FloatParam,
+    #[id = "emphasis_frequency"]
+    pub emphasis_frequency: FloatParam,
+    #[id = "dc_filter"]
+    pub dc_filter: EnumParam<DcFilter>,
+    #[id = "mix"]
+    pub mix: FloatParam,
+    #[id = "output_gain"]
+    pub output_gain: FloatParam,
+    #[id = "gain_compensation"]
+    pub gain_compensation: EnumParam<GainCompensation>,
+    /// Outputs the difference between the dry signal and the processed signal, which is mostly
+    /// what the clipping removes.
+    #[id = "delta"]
+    pub delta: BoolParam,
+    /// How much the channels are clipped together instead of on their own.
+    #[id = "stereo_link"]
+    pub stereo_link: FloatParam,
+    /// Which channels the stereo link links. The LFE channel is never linked.
+    #[id = "link_group"]
+    pub link_group: EnumParam<LinkGroup>,
+    /// Whether the LFE channel is clipped in surround layouts. Otherwise it skips the gain and the
+    /// clipping, and it's only delayed to stay in sync with the other channels.
+    #[id = "clip_lfe"]
+    pub clip_lfe: BoolParam,
+    /// Only applies to stereo audio.
+    #[id = "stereo_mode"]
+    pub stereo_mode: EnumParam<StereoMode>,
+    /// Offsets the threshold for the mid channel in the mid/side modes, in dB.
+    #[id = "mid_threshold_offset"]
+    pub mid_threshold_offset: FloatParam,
+    /// Offsets the threshold for the side channel in the mid/side modes, in dB.
+    #[id = "side_threshold_offset"]
+    pub side_threshold_offset: FloatParam,
+    /// The number of bands the signal is split into. A single band clips the whole signal at once.
+    #[id = "bands"]
+    pub band_count: IntParam,
+    #[id = "crossover"]
+    pub crossover: EnumParam<CrossoverType>,
+    /// The crossover frequencies in Hz. These get sorted, so they can't cross each other. Only the
+    /// first `band_count - 1` are used.
+    #[id = "low_crossover"]
+    pub low_crossover: FloatParam,
+    #[id = "mid_crossover"]
+    pub mid_crossover: FloatParam,
+    #[id = "high_crossover"]
+    pub high_crossover: FloatParam,
+    #[nested(array, group = "Band")]
+    pub bands: [BandParams; MAX_BANDS],
+    /// Keeps the lows below `protect_lows_frequency` out of the clipping.
+    #[id = "protect_lows"]
+    pub protect_lows: BoolParam,
+    #[id = "protect_lows_frequency"]
+    pub protect_lows_frequency: FloatParam,
     #[nested(id_prefix = "aa", group = "oversampling")]
     pub antialiasing: AntialiasingParams,
     #[persist = "editor-state"]
@@ -97,22 +405,129 @@ pub struct KlypParams {
 pub struct AntialiasingParams {
     #[id = "oversampling"]
     pub oversampling: IntParam,
-    #[id = "antiderivative"]
-    pub antiderivative: BoolParam,
+    #[id = "antiderivative_order"]
+    pub antiderivative: EnumParam<AntiderivativeOrder>,
+    #[id = "quality"]
+    pub quality: EnumParam<FilterQuality>,
+    #[id = "phase"]
+    pub phase: EnumParam<FilterPhase>,
+    /// Replaces `oversampling` while rendering offline. -1 keeps the regular oversampling factor.
+    #[id = "render_oversampling"]
+    pub render_oversampling: IntParam,
+    #[id = "render_antiderivative_order"]
+    pub render_antiderivative: EnumParam<RenderAntiderivativeOrder>,
+    /// Always report the worst-case latency, so the oversampling, the antiderivative order and the
+    /// crossover can be changed without the latency changing.
+    #[id = "constant_latency"]
+    pub constant_latency: BoolParam,
+}
+
+/// The settings for one band while the signal is split into bands. These are relative to the
+/// main threshold and softness, which still control every band at once.
+#[derive(Params)]
+pub struct BandParams {
+    /// Offsets the band's threshold, in dB.
+    #[id = "threshold_offset"]
+    pub threshold_offset: FloatParam,
+    /// Gets added to the band's softness.
+    #[id = "softness_offset"]
+    pub softness_offset: FloatParam,
 }
 
 struct ScratchBuffers {
-    gain: [f32; MAX_OVERSAMPLED_BLOCK_SIZE],
+    /// The gain is applied before oversampling, so it's smoothed at the base sample rate.
+    gain: [f32; BLOCK_SIZE],
+    /// The gain delayed by the reported latency, for `GainCompensation::PreGain`.
+    delayed_gain: [f32; BLOCK_SIZE],
+    /// How far `Klyp::chains` have been faded in for each sample while crossfading, from 0 to 1.
+    crossfade_mix: [f32; BLOCK_SIZE],
     threshold: [f32; MAX_OVERSAMPLED_BLOCK_SIZE],
     softness: [f32; MAX_OVERSAMPLED_BLOCK_SIZE],
+    negative_threshold: [f32; MAX_OVERSAMPLED_BLOCK_SIZE],
+    negative_softness: [f32; MAX_OVERSAMPLED_BLOCK_SIZE],
+    bias: [f32; MAX_OVERSAMPLED_BLOCK_SIZE],
+    stereo_link: [f32; MAX_OVERSAMPLED_BLOCK_SIZE],
+    mix: [f32; BLOCK_SIZE],
+    output_gain: [f32; BLOCK_SIZE],
+    mid_threshold_offset: [f32; BLOCK_SIZE],
+    side_threshold_offset: [f32; BLOCK_SIZE],
+    threshold_offset: [f32; MAX_OVERSAMPLED_BLOCK_SIZE],
+    softness_offset: [f32; MAX_OVERSAMPLED_BLOCK_SIZE],
+    bands: [BandBuffers; MAX_BANDS],
+    /// The block's channels, which get replaced by the sum of the bands processed by
+    /// `Klyp::chains`.
+    channels: [[f32; BLOCK_SIZE]; MAX_CHANNELS],
+    /// The sum of the bands processed by `Klyp::previous_chains` while crossfading.
+    previous_channels: [[f32; BLOCK_SIZE]; MAX_CHANNELS],
+    /// The block's channels split into bands.
+    band_channels: [[[f32; BLOCK_SIZE]; MAX_CHANNELS]; MAX_BANDS],
+    /// A copy of a band's channels for one of `Klyp::previous_chains`.
+    previous_band_channels: [[f32; BLOCK_SIZE]; MAX_CHANNELS],
+    /// How much of the lows `Klyp::low_split` splits off for each sample.
+    protect_lows_mix: [f32; BLOCK_SIZE],
+    /// The lows from `Klyp::low_split`, before they're delayed.
+    lows: [[f32; BLOCK_SIZE]; MAX_CHANNELS],
+}
+
+/// A band's smoothed threshold and softness, with the band's offsets applied.
+#[derive(Clone, Copy)]
+struct BandBuffers {
+    threshold: [f32; MAX_OVERSAMPLED_BLOCK_SIZE],
+    softness: [f32; MAX_OVERSAMPLED_BLOCK_SIZE],
+    negative_threshold: [f32; MAX_OVERSAMPLED_BLOCK_SIZE],
+    negative_softness: [f32; MAX_OVERSAMPLED_BLOCK_SIZE],
+}
+
+/// The state of switching from `Klyp::previous_chains` to `Klyp::chains`.
+#[derive(Debug, Clone, Copy)]
+struct Crossfade {
+    /// The new chain starts out with empty filter histories. Its output is only faded in after
+    /// this many samples, once it has settled.
+    settle_samples: usize,
+    /// How far along the crossfade is, in samples.
+    position: usize,
+    /// The latency that was reported for `Klyp::previous_chains`. Everything that's delayed to
+    /// line up with the clipped signal is crossfaded from this latency to the current one along
+    /// with the chains.
+    previous_latency: usize,
 }
 
 impl Default for ScratchBuffers {
     fn default() -> Self {
         Self {
-            gain: [0.0; MAX_OVERSAMPLED_BLOCK_SIZE],
+            gain: [0.0; BLOCK_SIZE],
+            delayed_gain: [0.0; BLOCK_SIZE],
+            crossfade_mix: [1.0; BLOCK_SIZE],
             threshold: [1.0; MAX_OVERSAMPLED_BLOCK_SIZE],
             softness: [0.0; MAX_OVERSAMPLED_BLOCK_SIZE],
+            negative_threshold: [1.0; MAX_OVERSAMPLED_BLOCK_SIZE],
+            negative_softness: [0.0; MAX_OVERSAMPLED_BLOCK_SIZE],
+            bias: [0.0; MAX_OVERSAMPLED_BLOCK_SIZE],
+            stereo_link: [0.0; MAX_OVERSAMPLED_BLOCK_SIZE],
+            mix: [1.0; BLOCK_SIZE],
+            output_gain: [0.0; BLOCK_SIZE],
+            mid_threshold_offset: [0.0; BLOCK_SIZE],
+            side_threshold_offset: [0.0; BLOCK_SIZE],
+            threshold_offset: [0.0; MAX_OVERSAMPLED_BLOCK_SIZE],
+            softness_offset: [0.0; MAX_OVERSAMPLED_BLOCK_SIZE],
+            bands: [BandBuffers::default(); MAX_BANDS],
+            channels: [[0.0; BLOCK_SIZE]; MAX_CHANNELS],
+            previous_channels: [[0.0; BLOCK_SIZE]; MAX_CHANNELS],
+            band_channels: [[[0.0; BLOCK_SIZE]; MAX_CHANNELS]; MAX_BANDS],
+            previous_band_channels: [[0.0; BLOCK_SIZE]; MAX_CHANNELS],
+            protect_lows_mix: [0.0; BLOCK_SIZE],
+            lows: [[0.0; BLOCK_SIZE]; MAX_CHANNELS],
+        }
+    }
+}
+
+impl Default for BandBuffers {
+    fn default() -> Self {
+        Self {
+            threshold: [1.0; MAX_OVERSAMPLED_BLOCK_SIZE],
+            softness: [0.0; MAX_OVERSAMPLED_BLOCK_SIZE],
+            negative_threshold: [1.0; MAX_OVERSAMPLED_BLOCK_SIZE],
+            negative_softness: [0.0; MAX_OVERSAMPLED_BLOCK_SIZE],
         }
     }
 }
@@ -123,10 +538,26 @@ impl Default for Klyp {
             params: Arc::new(KlypParams::default()),
             pre: Arc::new(Default::default()),
             post: Arc::new(Default::default()),
-            processors: vec![],
-            oversamplers: vec![],
+            crossover: Crossover::new(44100.0, 0),
+            low_split: LowSplit::new(44100.0, 0),
+            protect_lows_mix: 0.0,
+            low_delays: vec![],
+            chains: vec![],
+            previous_chains: vec![],
+            crossfade: None,
+            crossfade_length: 0,
+            dc_filter_mix: 0.0,
+            sample_rate: 44100.0,
+            dc_blockers: vec![],
+            dry_delays: vec![],
+            gain_delay: DelayLine::default(),
+            input_loudness: LoudnessMeter::default(),
+            output_loudness: LoudnessMeter::default(),
+            loudness_compensation: 1.0,
             scratch_buffers: Box::default(),
-            preferences: Default::default()
+            preferences: Default::default(),
+            layout: ChannelLayout::Stereo,
+            process_mode: ProcessMode::Realtime,
         }
     }
 }
@@ -162,6 +593,155 @@ impl Default for KlypParams {
                 .with_unit(" %")
                 .with_value_to_string(formatters::v2s_f32_percentage(2))
                 .with_string_to_value(formatters::s2v_f32_percentage()),
+            shape: EnumParam::new("Shape", ClipShape::default()),
+            link: BoolParam::new("Link Polarities", true),
+            negative_threshold: FloatParam::new(
+                "Negative Threshold",
+                1.0,
+                FloatRange::Skewed {
+                    min: MINUS_INFINITY_GAIN,
+                    max: 1.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+            negative_softness: FloatParam::new(
+                "Negative Softness",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_unit(" %")
+            .with_value_to_string(formatters::v2s_f32_percentage(2))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            bias: FloatParam::new(
+                "Bias",
+                0.0,
+                FloatRange::Linear {
+                    min: -1.0,
+                    max: 1.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            emphasis: FloatParam::new(
+                "Emphasis",
+                0.0,
+                FloatRange::Linear {
+                    min: -12.0,
+                    max: 12.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            emphasis_frequency: FloatParam::new(
+                "Emphasis Frequency",
+                1000.0,
+                FloatRange::Skewed {
+                    min: 100.0,
+                    max: 10000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            dc_filter: EnumParam::new("DC Filter", DcFilter::Auto),
+            mix: FloatParam::new("Mix", 1.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_unit(" %")
+                .with_value_to_string(formatters::v2s_f32_percentage(2))
+                .with_string_to_value(formatters::s2v_f32_percentage()),
+            output_gain: FloatParam::new(
+                "Output Gain",
+                0.0,
+                FloatRange::Linear {
+                    min: -24.0,
+                    max: 24.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            gain_compensation: EnumParam::new("Gain Compensation", GainCompensation::Off),
+            delta: BoolParam::new("Delta", false),
+            stereo_link: FloatParam::new(
+                "Stereo Link",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit(" %")
+            .with_value_to_string(formatters::v2s_f32_percentage(2))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            link_group: EnumParam::new("Link Group", LinkGroup::All),
+            clip_lfe: BoolParam::new("Clip LFE", false),
+            stereo_mode: EnumParam::new("Stereo Mode", StereoMode::LeftRight),
+            mid_threshold_offset: FloatParam::new(
+                "Mid Threshold Offset",
+                0.0,
+                FloatRange::Linear {
+                    min: -24.0,
+                    max: 24.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            side_threshold_offset: FloatParam::new(
+                "Side Threshold Offset",
+                0.0,
+                FloatRange::Linear {
+                    min: -24.0,
+                    max: 24.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            band_count: IntParam::new(
+                "Bands",
+                1,
+                IntRange::Linear {
+                    min: 1,
+                    max: MAX_BANDS as i32,
+                },
+            )
+            .with_value_to_string(Arc::new(|bands| {
+                if bands == 1 {
+                    String::from("1 Band")
+                } else {
+                    format!("{} Bands", bands)
+                }
+            }))
+            .with_string_to_value(Arc::new(|string| {
+                string
+                    .trim()
+                    .trim_end_matches(char::is_alphabetic)
+                    .trim()
+                    .parse()
+                    .ok()
+            })),
+            crossover: EnumParam::new("Crossover", CrossoverType::LinkwitzRiley),
+            low_crossover: crossover_frequency_param("Low Crossover", 120.0),
+            mid_crossover: crossover_frequency_param("Mid Crossover", 1000.0),
+            high_crossover: crossover_frequency_param("High Crossover", 5000.0),
+            bands: std::array::from_fn(BandParams::new),
+            protect_lows: BoolParam::new("Protect Lows", false),
+            protect_lows_frequency: FloatParam::new(
+                "Protect Lows Frequency",
+                80.0,
+                FloatRange::Skewed {
+                    min: 20.0,
+                    max: 500.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
             antialiasing: AntialiasingParams {
                 oversampling: IntParam::new(
                     "Oversampling",
@@ -171,23 +751,324 @@ impl Default for KlypParams {
                         max: MAX_OVERSAMPLING_FACTOR as i32,
                     },
                 )
-                .with_value_to_string(Arc::new(|x| format!("{}x", 2u32.pow(x as u32))))
+                .with_value_to_string(Arc::new(oversampling_to_string))
+                .with_string_to_value(Arc::new(string_to_oversampling)),
+                antiderivative: EnumParam::new("Antiderivative", AntiderivativeOrder::Auto),
+                quality: EnumParam::new("Filter Quality", FilterQuality::Draft),
+                phase: EnumParam::new("Filter Phase", FilterPhase::Linear),
+                render_oversampling: IntParam::new(
+                    "Render Oversampling",
+                    -1,
+                    IntRange::Linear {
+                        min: -1,
+                        max: MAX_OVERSAMPLING_FACTOR as i32,
+                    },
+                )
+                .with_value_to_string(Arc::new(|x| {
+                    if x < 0 {
+                        String::from("Same")
+                    } else {
+                        oversampling_to_string(x)
+                    }
+                }))
                 .with_string_to_value(Arc::new(|x| {
-                    x.parse::<i32>().map(|x| x.ilog2() as i32).ok()
+                    if x.trim().eq_ignore_ascii_case("same") {
+                        Some(-1)
+                    } else {
+                        string_to_oversampling(x)
+                    }
                 })),
-                antiderivative: BoolParam::new("Antiderivative", true),
+                render_antiderivative: EnumParam::new(
+                    "Render Antiderivative",
+                    RenderAntiderivativeOrder::Same,
+                ),
+                constant_latency: BoolParam::new("Constant Latency", false),
             },
             editor_state: editor::default_state(),
         }
     }
 }
 
+fn crossover_frequency_param(name: &str, default: f32) -> FloatParam {
+    FloatParam::new(
+        name,
+        default,
+        FloatRange::Skewed {
+            min: 20.0,
+            max: 20000.0,
+            factor: FloatRange::skew_factor(-2.0),
+        },
+    )
+    .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+    .with_string_to_value(formatters::s2v_f32_hz_then_khz())
+}
+
+impl BandParams {
+    /// The parameters for the band at `index`, counting from the lowest band.
+    fn new(index: usize) -> Self {
+        Self {
+            threshold_offset: FloatParam::new(
+                format!("Band {} Threshold Offset", index + 1),
+                0.0,
+                FloatRange::Linear {
+                    min: -24.0,
+                    max: 24.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            softness_offset: FloatParam::new(
+                format!("Band {} Softness Offset", index + 1),
+                0.0,
+                FloatRange::Linear {
+                    min: -1.0,
+                    max: 1.0,
+                },
+            )
+            .with_unit(" %")
+            .with_value_to_string(formatters::v2s_f32_percentage(2))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+        }
+    }
+}
+
+fn oversampling_to_string(factor: i32) -> String {
+    format!("{}x", 2u32.pow(factor as u32))
+}
+
+fn string_to_oversampling(string: &str) -> Option<i32> {
+    string
+        .trim()
+        .trim_end_matches('x')
+        .parse::<u32>()
+        .ok()
+        .filter(|x| x.is_power_of_two())
+        .map(|x| x.ilog2() as i32)
+}
+
+impl AntialiasingParams {
+    /// The oversampling factor to use. The render oversampling replaces the regular setting while
+    /// rendering offline, unless it is set to `Same`.
+    pub fn effective_oversampling(&self, offline: bool) -> usize {
+        let render_oversampling = self.render_oversampling.value();
+        if offline && render_oversampling >= 0 {
+            render_oversampling as usize
+        } else {
+            self.oversampling.value() as usize
+        }
+    }
+
+    /// The antiderivative to use, taking the render settings into account the same way as
+    /// [`effective_oversampling()`][Self::effective_oversampling()].
+    pub fn effective_antiderivative(&self, offline: bool) -> Antiderivative {
+        let order = self.antiderivative.value();
+        let order = if offline {
+            self.render_antiderivative.value().resolve(order)
+        } else {
+            order
+        };
+
+        order.resolve(self.effective_oversampling(offline))
+    }
+}
+
+impl KlypParams {
+    /// The negative half's threshold. Follows the positive threshold while the polarities are
+    /// linked.
+    pub fn negative_threshold(&self) -> f32 {
+        if self.link.value() {
+            self.threshold.value()
+        } else {
+            self.negative_threshold.value()
+        }
+    }
+
+    /// The negative half's softness. Follows the positive softness while the polarities are
+    /// linked.
+    pub fn negative_softness(&self) -> f32 {
+        if self.link.value() {
+            self.softness.value()
+        } else {
+            self.negative_softness.value()
+        }
+    }
+
+    /// The clipping curve described by the current, unsmoothed parameter values.
+    pub fn curve(&self) -> Curve {
+        Curve {
+            shape: self.shape.value(),
+            softness: self.softness.value() as f64,
+            negative_softness: self.negative_softness() as f64,
+            negative_threshold: (self.negative_threshold() / self.threshold.value()) as f64,
+        }
+    }
+
+    /// The crossover frequencies in Hz, see [`Crossover::set_frequencies()`].
+    pub fn crossover_frequencies(&self) -> [f32; MAX_BANDS - 1] {
+        [
+            self.low_crossover.value(),
+            self.mid_crossover.value(),
+            self.high_crossover.value(),
+        ]
+    }
+
+    /// The threshold for the band at `band`. The bands' offsets only apply while the signal is
+    /// split into multiple bands.
+    pub fn band_threshold(&self, band: usize) -> f32 {
+        if self.band_count.value() > 1 {
+            self.threshold.value() * db_to_gain(self.bands[band].threshold_offset.value())
+        } else {
+            self.threshold.value()
+        }
+    }
+
+    /// The clipping curve for the band at `band`, see
+    /// [`band_threshold()`][Self::band_threshold()].
+    pub fn band_curve(&self, band: usize) -> Curve {
+        let curve = self.curve();
+        if self.band_count.value() == 1 {
+            return curve;
+        }
+
+        let softness_offset = self.bands[band].softness_offset.value() as f64;
+        Curve {
+            softness: (curve.softness + softness_offset).clamp(0.0, 1.0),
+            negative_softness: (curve.negative_softness + softness_offset).clamp(0.0, 1.0),
+            ..curve
+        }
+    }
+
+    /// Whether the clipping is asymmetric, either because of the curve itself or because of the
+    /// bias. Asymmetric clipping produces a DC offset.
+    pub fn is_asymmetric(&self) -> bool {
+        !self.curve().is_symmetric() || self.bias.value() != 0.0
+    }
+
+    /// Whether the DC blocker should currently filter the output.
+    pub fn is_dc_filtered(&self) -> bool {
+        match self.dc_filter.value() {
+            DcFilter::Off => false,
+            DcFilter::Auto => self.is_asymmetric(),
+            DcFilter::On => true,
+        }
+    }
+}
+
+impl Klyp {
+    fn offline(&self) -> bool {
+        self.process_mode == ProcessMode::Offline
+    }
+
+    /// Switch the oversamplers to the currently selected filters.
+    fn update_filters(&mut self) {
+        let quality = self.params.antialiasing.quality.value();
+        let phase = self.params.antialiasing.phase.value();
+        for chain in self
+            .chains
+            .iter_mut()
+            .chain(self.previous_chains.iter_mut())
+        {
+            chain.set_filters(quality, phase);
+        }
+    }
+
+    /// Switch the crossover to the selected bands, and move the low split along with it. The chains
+    /// are reset when the number of bands or the crossover type changes, since they were clipping
+    /// other bands before. The bands' offsets are only smoothed while they're in use, so their
+    /// smoothers are snapped to the current values as well.
+    fn update_bands(&mut self) {
+        let bands = self.params.band_count.value() as usize;
+        let crossover_type = self.params.crossover.value();
+
+        if self.crossover.set_bands(bands, crossover_type) {
+            for chain in self
+                .chains
+                .iter_mut()
+                .chain(self.previous_chains.iter_mut())
+            {
+                chain.reset();
+            }
+            self.crossfade = None;
+
+            for band in &self.params.bands {
+                for offset in [&band.threshold_offset, &band.softness_offset] {
+                    offset.smoothed.reset(offset.value());
+                }
+            }
+        }
+        self.crossover
+            .set_frequencies(self.params.crossover_frequencies());
+        self.low_split.set_frequencies(
+            self.params.protect_lows_frequency.value(),
+            self.crossover.allpass_frequencies(),
+        );
+    }
+
+    /// Compute the latency to report to the host for the current chains and crossover. In
+    /// constant latency mode the chains and the crossover report their worst-case latency, and the
+    /// difference is made up by padding the chains' output, so changing the oversampling, the
+    /// antiderivative order, the number of bands or the crossover type doesn't change the latency.
+    /// The dry signal and the protected lows are delayed by the total latency.
+    /// `update_filters()` and `update_bands()` need to be called first.
+    fn update_latency(&mut self) -> u32 {
+        let latency = self.chains[0].latency() + self.crossover.latency();
+        let reported_latency = if self.params.antialiasing.constant_latency.value() {
+            self.chains[0].max_latency() + self.crossover.max_latency()
+        } else {
+            latency
+        };
+
+        for chain in &mut self.chains {
+            chain.set_padding(reported_latency - latency);
+        }
+
+        for dry_delay in &mut self.dry_delays {
+            dry_delay.set_delay(reported_latency as usize);
+        }
+        self.gain_delay.set_delay(reported_latency as usize);
+        for low_delay in &mut self.low_delays {
+            low_delay.set_delay(reported_latency as usize);
+        }
+
+        reported_latency
+    }
+
+    /// Start crossfading to the given settings if they differ from the current chains'. A change
+    /// during a crossfade is picked up once the crossfade has finished.
+    fn update_chains(&mut self, oversampling: usize, antiderivative: Antiderivative) {
+        if self.crossfade.is_some()
+            || (oversampling == self.chains[0].oversampling()
+                && antiderivative == self.chains[0].antiderivative())
+        {
+            return;
+        }
+
+        // The previous chains keep their padding. In constant latency mode both sets of chains are
+        // padded to the same latency, so the crossfade is sample-aligned. Otherwise the latency
+        // itself changes, so the dry signal, the delayed gain and the protected lows are read at
+        // both latencies and crossfaded the same way as the chains.
+        let previous_latency = self.update_latency() as usize;
+        std::mem::swap(&mut self.chains, &mut self.previous_chains);
+        for chain in &mut self.chains {
+            chain.configure(oversampling, antiderivative);
+        }
+        self.update_latency();
+
+        self.crossfade = Some(Crossfade {
+            settle_samples: 2 * self.chains[0].total_latency() as usize + CROSSFADE_SETTLE_SAMPLES,
+            position: 0,
+            previous_latency,
+        });
+    }
+}
+
 impl Plugin for Klyp {
     fn initialize(
         &mut self,
         audio_io_layout: &AudioIOLayout,
         buffer_config: &BufferConfig,
-        _context: &mut impl InitContext<Self>,
+        context: &mut impl InitContext<Self>,
     ) -> bool {
         let channels = audio_io_layout
             .main_input_channels
@@ -197,14 +1078,110 @@ impl Plugin for Klyp {
         self.pre.set_sample_rate(buffer_config.sample_rate);
         self.post.set_sample_rate(buffer_config.sample_rate);
 
-        self.processors = vec![Processor::default(); channels];
-        self.oversamplers.resize_with(channels, || {
-            Lanczos3Oversampler::new(BLOCK_SIZE, MAX_OVERSAMPLING_FACTOR)
-        });
+        let Some(layout) = ChannelLayout::from_channels(channels) else {
+            return false;
+        };
+        if channels > MAX_CHANNELS {
+            return false;
+        }
+        self.layout = layout;
+
+        // The padding and the dry signal's delay need to be able to make up the difference for any
+        // of the filters and crossovers
+        self.crossover = Crossover::new(buffer_config.sample_rate, channels);
+        let max_latency = Lanczos3Oversampler::max_latency(MAX_OVERSAMPLING_FACTOR)
+            + Antiderivative::ThirdDegree.latency(0)
+            + self.crossover.max_latency();
+        self.chains = (0..MAX_BANDS)
+            .map(|_| ClipperChain::new(channels, max_latency))
+            .collect();
+        self.previous_chains = (0..MAX_BANDS)
+            .map(|_| ClipperChain::new(channels, max_latency))
+            .collect();
+        self.crossfade = None;
+        self.crossfade_length = (CROSSFADE_TIME * buffer_config.sample_rate).round() as usize;
+        self.sample_rate = buffer_config.sample_rate;
+        self.dc_blockers =
+            vec![DcBlocker::new(buffer_config.sample_rate, DC_BLOCKER_CUTOFF); channels];
+        self.dry_delays = vec![DelayLine::new(max_latency as usize); channels];
+        self.gain_delay = DelayLine::new(max_latency as usize);
+        self.low_split = LowSplit::new(buffer_config.sample_rate, channels);
+        self.low_delays = vec![DelayLine::new(max_latency as usize); channels];
+        self.input_loudness =
+            LoudnessMeter::new(buffer_config.sample_rate, channels, LOUDNESS_MATCHING_TIME);
+        self.output_loudness =
+            LoudnessMeter::new(buffer_config.sample_rate, channels, LOUDNESS_MATCHING_TIME);
+        self.loudness_compensation = 1.0;
+
+        // Hosts reinitialize the plugin before an offline render, so the render settings take
+        // effect from the first block onwards. Their latency is reported right away, so the host
+        // can already compensate for it when the render starts instead of the latency changing
+        // during the first block.
+        self.process_mode = buffer_config.process_mode;
+        self.update_filters();
+        self.update_bands();
+
+        let offline = self.offline();
+        let oversampling = self.params.antialiasing.effective_oversampling(offline);
+        let antiderivative = self.params.antialiasing.effective_antiderivative(offline);
+        for chain in &mut self.chains {
+            chain.configure(oversampling, antiderivative);
+        }
+        context.set_latency_samples(self.update_latency());
 
         true
     }
 
+    fn reset(&mut self) {
+        // Stale samples would otherwise show up as spikes in the antiderivatives' divided
+        // differences once the host starts processing again
+        self.crossover.reset();
+        self.low_split.reset();
+        for chain in self
+            .chains
+            .iter_mut()
+            .chain(self.previous_chains.iter_mut())
+        {
+            chain.reset();
+        }
+        self.crossfade = None;
+        for dc_blocker in &mut self.dc_blockers {
+            dc_blocker.reset();
+        }
+        for dry_delay in &mut self.dry_delays {
+            dry_delay.reset();
+        }
+        self.gain_delay.reset();
+        for low_delay in &mut self.low_delays {
+            low_delay.reset();
+        }
+        self.protect_lows_mix = if self.params.protect_lows.value() {
+            1.0
+        } else {
+            0.0
+        };
+        self.input_loudness.reset();
+        self.output_loudness.reset();
+        self.loudness_compensation = 1.0;
+        self.dc_filter_mix = if self.params.is_dc_filtered() {
+            1.0
+        } else {
+            0.0
+        };
+    }
+
+    fn filter_state(state: &mut PluginState) {
+        // Antiderivative antialiasing used to be a switch that picked the order from the
+        // oversampling factor, which is what the `Auto` order does now
+        if let Some(ParamValue::Bool(enabled)) = state.params.remove("aa_antiderivative") {
+            let order = if enabled { "auto" } else { "off" };
+            state
+                .params
+                .entry(String::from("aa_antiderivative_order"))
+                .or_insert_with(|| ParamValue::String(String::from(order)));
+        }
+    }
+
     fn process(
         &mut self,
         buffer: &mut Buffer,
@@ -213,36 +1190,105 @@ impl Plugin for Klyp {
     ) -> ProcessStatus {
         let gui_open = self.params.editor_state.is_open();
 
-        let oversampling = self.params.antialiasing.oversampling.value() as usize;
+        let offline = self.offline();
+        let oversampling = self.params.antialiasing.effective_oversampling(offline);
+        let antiderivative = self.params.antialiasing.effective_antiderivative(offline);
 
-        let antiderivative = if self.params.antialiasing.antiderivative.value() {
-            match oversampling {
-                0 => Antiderivative::Off,
-                1 => Antiderivative::FirstDegree,
-                2.. => Antiderivative::SecondDegree,
-            }
+        self.update_filters();
+        self.update_bands();
+        self.update_chains(oversampling, antiderivative);
+        context.set_latency_samples(self.update_latency());
+
+        // The parameters are smoothed at the rate of the chains that are being switched to
+        let oversampling = self.chains[0].oversampling();
+        let bands = self.crossover.bands();
+
+        let shape = self.params.shape.value();
+        let polarities_linked = self.params.link.value();
+
+        let dc_filter = if self.params.is_dc_filtered() {
+            1.0
         } else {
-            Antiderivative::Off
+            0.0
+        };
+        let crossfade_length = self.crossfade_length.max(1) as f32;
+        let gain_compensation = self.params.gain_compensation.value();
+        let delta = self.params.delta.value();
+        let protect_lows = if self.params.protect_lows.value() {
+            1.0
+        } else {
+            0.0
         };
 
-        let mut latency = 0;
+        let stereo_mode = self.params.stereo_mode.value();
+        let mid_side = stereo_mode != StereoMode::LeftRight && self.layout == ChannelLayout::Stereo;
+        let link_group = self.params.link_group.value();
 
-        if let Some(oversampler) = self.oversamplers.first() {
-            latency += oversampler.latency(oversampling);
+        let mut bypassed = [false; MAX_CHANNELS];
+        let mut linked = [false; MAX_CHANNELS];
+        for (channel, linked) in linked.iter_mut().enumerate().take(buffer.channels()) {
+            *linked = match link_group {
+                LinkGroup::All => true,
+                LinkGroup::Front => self.layout.is_front(channel),
+            };
         }
-
-        if antiderivative != Antiderivative::Off {
-            latency += 1;
+        if let Some(lfe) = self.layout.lfe() {
+            bypassed[lfe] = !self.params.clip_lfe.value();
+            linked[lfe] = false;
         }
-
-        context.set_latency_samples(latency);
+        // The first channel holds the mid and the second one the side
+        if mid_side {
+            bypassed[0] = stereo_mode == StereoMode::Side;
+            bypassed[1] = stereo_mode == StereoMode::Mid;
+        }
+        let (min_compensation, max_compensation) = (
+            db_to_gain(-MAX_LOUDNESS_COMPENSATION),
+            db_to_gain(MAX_LOUDNESS_COMPENSATION),
+        );
 
         for (_, mut block) in buffer.iter_blocks(BLOCK_SIZE) {
             let samples = block.samples();
             let samples_upscaled = samples * (1 << oversampling);
 
+            // The crossfade's progress is the same for every channel and for every signal that
+            // needs to line up with the chains' output
+            let crossfade = self.crossfade;
+            let previous_latency = crossfade.map(|crossfade| crossfade.previous_latency);
+            let crossfade_mix = &mut self.scratch_buffers.crossfade_mix;
+            if let Some(crossfade) = &mut self.crossfade {
+                for mix in &mut crossfade_mix[..samples] {
+                    *mix = if crossfade.settle_samples > 0 {
+                        crossfade.settle_samples -= 1;
+                        0.0
+                    } else {
+                        crossfade.position += 1;
+                        (crossfade.position as f32 / self.crossfade_length as f32).min(1.0)
+                    };
+                }
+
+                if crossfade.position >= self.crossfade_length {
+                    self.crossfade = None;
+                }
+            }
+            let crossfade_mix = &*crossfade_mix;
+
             let gain = &mut self.scratch_buffers.gain;
-            self.params.gain.smoothed.next_block(gain, samples_upscaled);
+            self.params.gain.smoothed.next_block(gain, samples);
+
+            let delayed_gain = &mut self.scratch_buffers.delayed_gain;
+            for (i, (delayed_gain, gain)) in delayed_gain
+                .iter_mut()
+                .zip(gain.iter())
+                .take(samples)
+                .enumerate()
+            {
+                *delayed_gain = delay_crossfaded(
+                    &mut self.gain_delay,
+                    *gain,
+                    previous_latency,
+                    crossfade_mix[i],
+                );
+            }
 
             let threshold = &mut self.scratch_buffers.threshold;
             self.params
@@ -256,60 +1302,354 @@ impl Plugin for Klyp {
                 .smoothed
                 .next_block(softness, samples_upscaled);
 
+            let negative_threshold = &mut self.scratch_buffers.negative_threshold;
+            self.params
+                .negative_threshold
+                .smoothed
+                .next_block(negative_threshold, samples_upscaled);
+
+            let negative_softness = &mut self.scratch_buffers.negative_softness;
+            self.params
+                .negative_softness
+                .smoothed
+                .next_block(negative_softness, samples_upscaled);
+
+            let bias = &mut self.scratch_buffers.bias;
+            self.params.bias.smoothed.next_block(bias, samples_upscaled);
+
+            let stereo_link = &mut self.scratch_buffers.stereo_link;
+            self.params
+                .stereo_link
+                .smoothed
+                .next_block(stereo_link, samples_upscaled);
+
+            let mix = &mut self.scratch_buffers.mix;
+            self.params.mix.smoothed.next_block(mix, samples);
+
+            let output_gain = &mut self.scratch_buffers.output_gain;
+            self.params
+                .output_gain
+                .smoothed
+                .next_block(output_gain, samples);
+
+            let mid_threshold_offset = &mut self.scratch_buffers.mid_threshold_offset;
+            self.params
+                .mid_threshold_offset
+                .smoothed
+                .next_block(mid_threshold_offset, samples);
+
+            let side_threshold_offset = &mut self.scratch_buffers.side_threshold_offset;
+            self.params
+                .side_threshold_offset
+                .smoothed
+                .next_block(side_threshold_offset, samples);
+
+            // The filters' coefficients are only updated once per block
+            let emphasis = Emphasis::new(
+                self.sample_rate,
+                self.params
+                    .emphasis_frequency
+                    .smoothed
+                    .next_step(samples as u32),
+                self.params.emphasis.smoothed.next_step(samples as u32),
+            );
+
+            let (negative_threshold, negative_softness) = if polarities_linked {
+                (&*threshold, &*softness)
+            } else {
+                (&*negative_threshold, &*negative_softness)
+            };
+
+            // A single band uses the main threshold and softness as they are
+            if bands > 1 {
+                for (band_params, band) in self
+                    .params
+                    .bands
+                    .iter()
+                    .zip(self.scratch_buffers.bands.iter_mut())
+                    .take(bands)
+                {
+                    let threshold_offset = &mut self.scratch_buffers.threshold_offset;
+                    band_params
+                        .threshold_offset
+                        .smoothed
+                        .next_block(threshold_offset, samples_upscaled);
+
+                    let softness_offset = &mut self.scratch_buffers.softness_offset;
+                    band_params
+                        .softness_offset
+                        .smoothed
+                        .next_block(softness_offset, samples_upscaled);
+
+                    for i in 0..samples_upscaled {
+                        let offset_gain = db_to_gain_fast(threshold_offset[i]);
+                        band.threshold[i] = threshold[i] * offset_gain;
+                        band.negative_threshold[i] = negative_threshold[i] * offset_gain;
+                        band.softness[i] = (softness[i] + softness_offset[i]).clamp(0.0, 1.0);
+                        band.negative_softness[i] =
+                            (negative_softness[i] + softness_offset[i]).clamp(0.0, 1.0);
+                    }
+                }
+            }
+
             if gui_open {
-                let channels = block.channels() as f32;
+                let layout = self.layout;
+                let channels = (0..block.channels())
+                    .filter(|channel| layout.is_displayed(*channel))
+                    .count() as f32;
                 for (i, sample) in block.iter_samples().enumerate() {
                     let gain = unsafe { db_to_gain_fast(*gain.get_unchecked(i)) };
                     if gain.is_infinite() {
                         panic!();
                     }
-                    self.pre
-                        .send(sample.into_iter().map(|x| *x).sum::<f32>() / channels * gain);
+                    let sum = sample
+                        .into_iter()
+                        .enumerate()
+                        .filter(|(channel, _)| layout.is_displayed(*channel))
+                        .map(|(_, x)| *x)
+                        .sum::<f32>();
+                    self.pre.send(sum / channels * gain);
                 }
             }
 
-            for (block_channel, (oversampler, processor)) in block
-                .into_iter()
-                .zip(self.oversamplers.iter_mut().zip(self.processors.iter_mut()))
-            {
-                for (i, sample) in block_channel.iter_mut().enumerate() {
-                    let gain = unsafe { db_to_gain_fast(*gain.get_unchecked(i)) };
-                    let threshold = unsafe { threshold.get_unchecked(i) };
-                    *sample *= gain;
-                    *sample /= threshold;
+            let channels = block.channels();
+            let buffers = &mut self.scratch_buffers.channels[..channels];
+            for (channel, buffer) in buffers.iter_mut().enumerate() {
+                buffer[..samples].copy_from_slice(unsafe { block.get_unchecked_mut(channel) });
+            }
+            // The meters keep running in every mode, so switching to loudness matching doesn't
+            // have to wait for them to settle
+            self.input_loudness.process(buffers, samples);
+            if mid_side {
+                encode_mid_side(
+                    buffers,
+                    samples,
+                    mid_threshold_offset,
+                    side_threshold_offset,
+                );
+            }
+
+            // Switching the split on or off changes which parts of the signal get clipped, and
+            // the lows' phase shifts don't null with the full signal, so the split gets faded in
+            // and out instead
+            let previous_protect_lows_mix = self.protect_lows_mix;
+            let protect_lows_mix = &mut self.scratch_buffers.protect_lows_mix;
+            for (i, mix) in protect_lows_mix[..samples].iter_mut().enumerate() {
+                let step = (i + 1) as f32 / crossfade_length;
+                *mix = if protect_lows > previous_protect_lows_mix {
+                    (previous_protect_lows_mix + step).min(protect_lows)
+                } else {
+                    (previous_protect_lows_mix - step).max(protect_lows)
+                };
+            }
+            self.protect_lows_mix = protect_lows_mix[samples - 1];
+
+            let lows = &mut self.scratch_buffers.lows[..channels];
+            self.low_split
+                .process(buffers, samples, lows, &protect_lows_mix[..samples]);
+
+            let band_channels = &mut self.scratch_buffers.band_channels[..bands];
+            if bands > 1 {
+                self.crossover.process(buffers, samples, band_channels);
+            } else {
+                band_channels[0][..channels].copy_from_slice(buffers);
+            }
+
+            // The bands are summed back up after they've been clipped
+            let previous_buffers = &mut self.scratch_buffers.previous_channels[..channels];
+            buffers.fill([0.0; BLOCK_SIZE]);
+            if crossfade.is_some() {
+                previous_buffers.fill([0.0; BLOCK_SIZE]);
+            }
+
+            for (band, band_channels) in band_channels.iter_mut().enumerate() {
+                let band_channels = &mut band_channels[..channels];
+                let (threshold, softness, negative_threshold, negative_softness) = if bands > 1 {
+                    let band = &self.scratch_buffers.bands[band];
+                    (
+                        &band.threshold[..],
+                        &band.softness[..],
+                        &band.negative_threshold[..],
+                        &band.negative_softness[..],
+                    )
+                } else {
+                    (
+                        &threshold[..],
+                        &softness[..],
+                        &negative_threshold[..],
+                        &negative_softness[..],
+                    )
+                };
+                let parameters = BlockParameters {
+                    oversampling,
+                    shape,
+                    gain,
+                    threshold,
+                    softness,
+                    negative_softness,
+                    negative_threshold,
+                    bias,
+                    emphasis,
+                    stereo_link,
+                    linked,
+                    bypassed,
+                };
+
+                if crossfade.is_some() {
+                    let previous_band_channels =
+                        &mut self.scratch_buffers.previous_band_channels[..channels];
+                    previous_band_channels.copy_from_slice(band_channels);
+                    self.previous_chains[band].process(
+                        previous_band_channels,
+                        samples,
+                        &parameters,
+                    );
+                    add_channels(previous_buffers, previous_band_channels, samples);
                 }
-                oversampler.process(block_channel, oversampling, |upsampled| {
-                    for (i, sample) in upsampled.iter_mut().enumerate() {
-                        let softness = unsafe { softness.get_unchecked(i) };
 
-                        *sample =
-                            processor.process(*sample as f64, *softness as f64, &antiderivative)
-                                as f32;
+                self.chains[band].process(band_channels, samples, &parameters);
+                add_channels(buffers, band_channels, samples);
+            }
+
+            if crossfade.is_some() {
+                for (buffer, previous_buffer) in buffers.iter_mut().zip(previous_buffers.iter()) {
+                    for ((sample, previous_sample), mix) in buffer[..samples]
+                        .iter_mut()
+                        .zip(previous_buffer[..samples].iter())
+                        .zip(crossfade_mix.iter())
+                    {
+                        *sample = previous_sample + (*sample - previous_sample) * mix;
                     }
-                });
-                for (i, sample) in block_channel.iter_mut().enumerate() {
-                    let threshold = unsafe { threshold.get_unchecked(i) };
-                    *sample *= threshold;
+                }
+            }
+
+            // The lows are delayed by the same latency as the clipped highs. They keep being added
+            // while the split is off, since the delay lines may still hold lows from before.
+            for ((buffer, lows), low_delay) in buffers
+                .iter_mut()
+                .zip(lows.iter())
+                .zip(self.low_delays.iter_mut())
+            {
+                for (i, (sample, low)) in buffer[..samples]
+                    .iter_mut()
+                    .zip(lows[..samples].iter())
+                    .enumerate()
+                {
+                    *sample +=
+                        delay_crossfaded(low_delay, *low, previous_latency, crossfade_mix[i]);
+                }
+            }
+
+            if mid_side {
+                decode_mid_side(
+                    buffers,
+                    samples,
+                    mid_threshold_offset,
+                    side_threshold_offset,
+                );
+            }
+
+            // The DC blocker is minimum-phase, so it doesn't affect the latency. It keeps running
+            // while it's disabled so it can be switched on without having to settle first. Its
+            // output is faded in and out, since `DcFilter::Auto` switches it whenever the
+            // clipping's symmetry changes.
+            let previous_dc_filter_mix = self.dc_filter_mix;
+            let dc_filter_mix = |i: usize| {
+                let step = (i + 1) as f32 / crossfade_length;
+                if dc_filter > previous_dc_filter_mix {
+                    (previous_dc_filter_mix + step).min(dc_filter)
+                } else {
+                    (previous_dc_filter_mix - step).max(dc_filter)
+                }
+            };
+            for (buffer, dc_blocker) in buffers.iter_mut().zip(self.dc_blockers.iter_mut()) {
+                for (i, sample) in buffer[..samples].iter_mut().enumerate() {
+                    let filtered = dc_blocker.process(*sample);
+                    *sample += (filtered - *sample) * dc_filter_mix(i);
+                }
+            }
+            self.dc_filter_mix = dc_filter_mix(samples - 1);
+
+            self.output_loudness.process(buffers, samples);
+            let previous_compensation = self.loudness_compensation;
+            let input_power = self.input_loudness.power();
+            let output_power = self.output_loudness.power();
+            if input_power > LOUDNESS_GATE && output_power > LOUDNESS_GATE {
+                self.loudness_compensation = (input_power / output_power)
+                    .sqrt()
+                    .clamp(min_compensation, max_compensation);
+            }
+
+            for (channel, (buffer, dry_delay)) in
+                buffers.iter().zip(self.dry_delays.iter_mut()).enumerate()
+            {
+                let block_channel = unsafe { block.get_unchecked_mut(channel) };
+
+                // The block still holds the dry signal at this point
+                for (i, (sample, wet)) in block_channel
+                    .iter_mut()
+                    .zip(buffer[..samples].iter())
+                    .enumerate()
+                {
+                    // The bypassed channels never got the gain, so there's nothing to compensate
+                    let compensation = match gain_compensation {
+                        _ if bypassed[channel] => 1.0,
+                        GainCompensation::Off => 1.0,
+                        // The wet signal was amplified by the gain from before the latency
+                        GainCompensation::PreGain => unsafe {
+                            db_to_gain_fast(-*delayed_gain.get_unchecked(i))
+                        },
+                        // The loudness is only measured once per block, so the gain is ramped
+                        // towards the new measurement over the block
+                        GainCompensation::Loudness => {
+                            previous_compensation
+                                + (self.loudness_compensation - previous_compensation)
+                                    * (i + 1) as f32
+                                    / samples as f32
+                        }
+                    };
+                    let (mix, output_gain) =
+                        unsafe { (*mix.get_unchecked(i), *output_gain.get_unchecked(i)) };
+
+                    let wet = wet * compensation;
+                    let dry =
+                        delay_crossfaded(dry_delay, *sample, previous_latency, crossfade_mix[i]);
+                    let mixed = dry + (wet - dry) * mix;
+
+                    // The dry signal is aligned with the processed signal, so this is mostly
+                    // what the clipping changed. Everything else that changes the signal ends up
+                    // in here as well, like uncompensated pre-gain, the DC blocker, emphasis
+                    // automation, the Linkwitz-Riley crossovers' phase shifts and the
+                    // oversampling filters' and antiderivatives' high-frequency rolloff. The mix
+                    // and the output gain scale it too.
+                    let output = if delta { dry - mixed } else { mixed };
+
+                    *sample = output * db_to_gain_fast(output_gain);
                 }
             }
         }
 
+        // In delta mode this shows the delta signal, since that's what's being output
         if gui_open {
-            self.post.send_buffer_summing(buffer);
+            let layout = self.layout;
+            let channels = (0..buffer.channels())
+                .filter(|channel| layout.is_displayed(*channel))
+                .count() as f32;
+            for sample in buffer.iter_samples() {
+                let sum = sample
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(channel, _)| layout.is_displayed(*channel))
+                    .map(|(_, x)| *x)
+                    .sum::<f32>();
+                self.post.send(sum / channels);
+            }
         }
 
         return ProcessStatus::Normal;
     }
 
-    const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[AudioIOLayout {
-        main_input_channels: NonZeroU32::new(2),
-        main_output_channels: NonZeroU32::new(2),
-
-        aux_input_ports: &[],
-        aux_output_ports: &[],
-
-        names: PortNames::const_default(),
-    }];
+    const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &layout::AUDIO_IO_LAYOUTS;
 
     const MIDI_INPUT: MidiConfig = MidiConfig::None;
     const MIDI_OUTPUT: MidiConfig = MidiConfig::None;
@@ -347,7 +1687,12 @@ impl ClapPlugin for Klyp {
     const CLAP_MANUAL_URL: Option<&'static str> = Some(Self::URL);
     const CLAP_SUPPORT_URL: Option<&'static str> = None;
 
-    const CLAP_FEATURES: &'static [ClapFeature] = &[ClapFeature::AudioEffect, ClapFeature::Stereo];
+    const CLAP_FEATURES: &'static [ClapFeature] = &[
+        ClapFeature::AudioEffect,
+        ClapFeature::Mono,
+        ClapFeature::Stereo,
+        ClapFeature::Surround,
+    ];
 }
 
 impl Vst3Plugin for Klyp {
@@ -359,3 +1704,297 @@ impl Vst3Plugin for Klyp {
 
 nih_export_clap!(Klyp);
 nih_export_vst3!(Klyp);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::collections::BTreeMap;
+    use std::f32::consts::PI;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    /// Keeps track of the latency the plugin reports, with a transport that's stopped.
+    struct TestContext {
+        latency: Cell<u32>,
+        transport: Transport,
+    }
+
+    impl InitContext<Klyp> for TestContext {
+        fn plugin_api(&self) -> PluginApi {
+            PluginApi::Clap
+        }
+
+        fn execute(&self, _task: ()) {}
+
+        fn set_latency_samples(&self, samples: u32) {
+            self.latency.set(samples);
+        }
+
+        fn set_current_voice_capacity(&self, _capacity: u32) {}
+    }
+
+    impl ProcessContext<Klyp> for TestContext {
+        fn plugin_api(&self) -> PluginApi {
+            PluginApi::Clap
+        }
+
+        fn execute_background(&self, _task: ()) {}
+
+        fn execute_gui(&self, _task: ()) {}
+
+        fn transport(&self) -> &Transport {
+            &self.transport
+        }
+
+        fn next_event(&mut self) -> Option<PluginNoteEvent<Klyp>> {
+            None
+        }
+
+        fn send_event(&mut self, _event: PluginNoteEvent<Klyp>) {}
+
+        fn set_latency_samples(&self, samples: u32) {
+            self.latency.set(samples);
+        }
+
+        fn set_current_voice_capacity(&self, _capacity: u32) {}
+    }
+
+    /// Create and initialize a stereo instance with `params`. Like when the host initializes the
+    /// plugin, the smoothers are snapped to the parameters' values and the plugin gets reset.
+    fn klyp(params: KlypParams) -> (Klyp, TestContext) {
+        let bands = params
+            .bands
+            .iter()
+            .flat_map(|band| [&band.threshold_offset, &band.softness_offset]);
+        for param in [
+            &params.gain,
+            &params.threshold,
+            &params.softness,
+            &params.negative_threshold,
+            &params.negative_softness,
+            &params.bias,
+            &params.emphasis,
+            &params.emphasis_frequency,
+            &params.mix,
+            &params.output_gain,
+            &params.stereo_link,
+            &params.mid_threshold_offset,
+            &params.side_threshold_offset,
+        ]
+        .into_iter()
+        .chain(bands)
+        {
+            param.smoothed.reset(param.value());
+        }
+
+        let mut klyp = Klyp {
+            params: Arc::new(params),
+            ..Klyp::default()
+        };
+        let mut context = TestContext {
+            latency: Cell::new(0),
+            transport: Transport::new(SAMPLE_RATE),
+        };
+        let layout = Klyp::AUDIO_IO_LAYOUTS
+            .iter()
+            .find(|layout| layout.main_input_channels.map(|c| c.get()) == Some(2))
+            .unwrap();
+        let buffer_config = BufferConfig {
+            sample_rate: SAMPLE_RATE,
+            min_buffer_size: None,
+            max_buffer_size: 512,
+            process_mode: ProcessMode::Realtime,
+        };
+        assert!(klyp.initialize(layout, &buffer_config, &mut context));
+        klyp.reset();
+
+        (klyp, context)
+    }
+
+    /// Process `channels` in place in blocks of 64 samples. `before_block` gets called with the
+    /// index of the first sample before every block.
+    fn process(
+        klyp: &mut Klyp,
+        context: &mut TestContext,
+        channels: &mut [Vec<f32>],
+        mut before_block: impl FnMut(&mut Klyp, usize),
+    ) {
+        let len = channels[0].len();
+        for start in (0..len).step_by(64) {
+            let end = (start + 64).min(len);
+            before_block(klyp, start);
+
+            let mut buffer = Buffer::default();
+            unsafe {
+                buffer.set_slices(end - start, |slices| {
+                    *slices = channels
+                        .iter_mut()
+                        .map(|channel| &mut channel[start..end])
+                        .collect();
+                });
+            }
+            let mut aux = AuxiliaryBuffers {
+                inputs: &mut [],
+                outputs: &mut [],
+            };
+            klyp.process(&mut buffer, &mut aux, context);
+        }
+    }
+
+    #[test]
+    fn switching_is_smooth() {
+        for protect_lows in [false, true] {
+            // Half of the output is the dry signal, and with `protect_lows` the low sine skips
+            // the clipping. Both need to stay lined up with the clipped signal while the latency
+            // changes.
+            let (mut klyp, mut context) = klyp(KlypParams {
+                mix: FloatParam::new("Mix", 0.5, FloatRange::Linear { min: 0.0, max: 1.0 }),
+                protect_lows: BoolParam::new("Protect Lows", protect_lows),
+                protect_lows_frequency: FloatParam::new(
+                    "Protect Lows Frequency",
+                    500.0,
+                    FloatRange::Linear {
+                        min: 20.0,
+                        max: 500.0,
+                    },
+                ),
+                ..KlypParams::default()
+            });
+
+            // Sines that stay below the threshold can't change faster than this from one sample
+            // to the next, no matter how much they're delayed
+            let sines = [(100.0, 0.5), (2000.0, 0.25)];
+            let max_step: f32 = sines
+                .iter()
+                .map(|(frequency, amplitude)| amplitude * 2.0 * PI * frequency / SAMPLE_RATE)
+                .sum();
+
+            // Every switch is followed by another one back to the parameters' settings once its
+            // crossfade has finished
+            let signal: Vec<f32> = (0..48000)
+                .map(|i| {
+                    sines
+                        .iter()
+                        .map(|(frequency, amplitude)| {
+                            amplitude * (2.0 * PI * frequency * i as f32 / SAMPLE_RATE).sin()
+                        })
+                        .sum()
+                })
+                .collect();
+            let mut channels = vec![signal; 2];
+            process(
+                &mut klyp,
+                &mut context,
+                &mut channels,
+                |klyp, start| match start {
+                    6400 => klyp.update_chains(2, Antiderivative::FirstDegree),
+                    19200 => klyp.update_chains(1, Antiderivative::ThirdDegree),
+                    32000 => klyp.update_chains(3, Antiderivative::Off),
+                    _ => (),
+                },
+            );
+
+            for channel in &channels {
+                for (i, step) in channel.windows(2).map(|w| (w[1] - w[0]).abs()).enumerate() {
+                    assert!(step < max_step * 1.05, "jump of {step} at sample {i}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reset_matches_fresh_instance() {
+        // Clip several bands with oversampling, emphasis and protected lows, so every filter and
+        // delay line along the way holds some state
+        let params = || KlypParams {
+            threshold: FloatParam::new(
+                "Threshold",
+                0.25,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            ),
+            emphasis: FloatParam::new(
+                "Emphasis",
+                6.0,
+                FloatRange::Linear {
+                    min: -12.0,
+                    max: 12.0,
+                },
+            ),
+            protect_lows: BoolParam::new("Protect Lows", true),
+            band_count: IntParam::new(
+                "Bands",
+                3,
+                IntRange::Linear {
+                    min: 1,
+                    max: MAX_BANDS as i32,
+                },
+            ),
+            antialiasing: AntialiasingParams {
+                oversampling: IntParam::new(
+                    "Oversampling",
+                    2,
+                    IntRange::Linear {
+                        min: 0,
+                        max: MAX_OVERSAMPLING_FACTOR as i32,
+                    },
+                ),
+                ..KlypParams::default().antialiasing
+            },
+            ..KlypParams::default()
+        };
+        let (mut used, mut used_context) = klyp(params());
+        let (mut fresh, mut fresh_context) = klyp(params());
+
+        let mut state = 1u32;
+        let noise: Vec<f32> = (0..24000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as f32 / u32::MAX as f32 - 0.5
+            })
+            .collect();
+        let mut noise_channels = [noise.clone(), noise];
+        process(&mut used, &mut used_context, &mut noise_channels, |_, _| ());
+        used.reset();
+
+        let signal: Vec<f32> = (0..24000)
+            .map(|i| 0.8 * (2.0 * PI * 440.0 * i as f32 / SAMPLE_RATE).sin())
+            .collect();
+        let mut used_channels = vec![signal; 2];
+        let mut fresh_channels = used_channels.clone();
+        process(&mut used, &mut used_context, &mut used_channels, |_, _| ());
+        process(
+            &mut fresh,
+            &mut fresh_context,
+            &mut fresh_channels,
+            |_, _| (),
+        );
+
+        assert_eq!(used_channels, fresh_channels);
+    }
+
+    #[test]
+    fn antiderivative_switch_is_migrated() {
+        for (enabled, order) in [(true, "auto"), (false, "off")] {
+            let mut state = PluginState {
+                version: String::from("1.0.0"),
+                params: BTreeMap::from([(
+                    String::from("aa_antiderivative"),
+                    ParamValue::Bool(enabled),
+                )]),
+                fields: BTreeMap::new(),
+            };
+            Klyp::filter_state(&mut state);
+
+            assert_eq!(
+                state.params,
+                BTreeMap::from([(
+                    String::from("aa_antiderivative_order"),
+                    ParamValue::String(String::from(order)),
+                )])
+            );
+        }
+    }
+}
@@ -15,9 +15,10 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use nih_plug::debug::*;
+use nih_plug::prelude::Enum;
 
-/// The kernel used in `Lanczos3Oversampler`. Specified here as a constant since it is a constant.
-/// Precomputed since compile-time floating point arithmetic is still unstable.
+/// The default kernel used in `Lanczos3Oversampler`. Specified here as a constant since it is a
+/// constant. Precomputed since compile-time floating point arithmetic is still unstable.
 ///
 /// Computed using:
 ///
@@ -44,30 +45,254 @@ const LANCZOS3_UPSAMPLING_KERNEL: [f32; 11] = [
     0.02431708,
 ];
 
-/// `LANCZOS3_UPSAMPLING_KERNEL` divided by two, used for downsampling so that upsampling followed
-/// by downsampling results in unity gain.
-const LANCZOS3_DOWNSAMPLING_KERNEL: [f32; 11] = [
-    0.01215854,
-    -0.0,
-    -0.06754746,
-    0.0,
-    0.30396355,
-    0.5,
-    0.30396355,
-    0.0,
-    -0.06754746,
-    -0.0,
-    0.01215854,
+/// The nonzero taps of `LANCZOS3_UPSAMPLING_KERNEL`, apart from the center tap. These are the
+/// polyphase component used for the even samples. The center tap is always 1.
+const LANCZOS3_TAPS: [f32; 6] = even_taps(&LANCZOS3_UPSAMPLING_KERNEL);
+
+/// The nonzero taps of a longer Kaiser-windowed sinc halfband kernel, in the same form as
+/// `LANCZOS3_TAPS`. This and the other kernels below were computed using:
+///
+/// ```python
+/// def halfband_taps(num_taps, beta):
+///     n = np.arange(num_taps) - num_taps // 2
+///     kernel = np.sinc(n / 2) * np.kaiser(num_taps, beta)
+///
+///     taps = kernel[::2]
+///     return taps / taps.sum()
+///
+/// halfband_taps(23, 5.65)
+/// ```
+///
+/// The taps are normalized to sum to one so the filter has unity gain at DC. This kernel attenuates
+/// everything above 0.332 times the oversampled sample rate by at least 58 dB.
+const NORMAL_TAPS: [f32; 12] = [
+    -0.0011829353,
+    0.008648862,
+    -0.02869465,
+    0.07298572,
+    -0.17475484,
+    0.6229978,
+    0.6229978,
+    -0.17475484,
+    0.07298572,
+    -0.02869465,
+    0.008648862,
+    -0.0011829353,
+];
+
+/// `halfband_taps(47, 8.96)`, which attenuates everything above 0.312 times the oversampled sample
+/// rate by at least 89 dB.
+const HIGH_TAPS: [f32; 24] = [
+    -2.6282498e-05,
+    0.00024199394,
+    -0.0009057378,
+    0.0024686318,
+    -0.005607469,
+    0.0112721715,
+    -0.020785408,
+    0.03614034,
+    -0.060964376,
+    0.104056254,
+    -0.19743861,
+    0.63154846,
+    0.63154846,
+    -0.19743861,
+    0.104056254,
+    -0.060964376,
+    0.03614034,
+    -0.020785408,
+    0.0112721715,
+    -0.005607469,
+    0.0024686318,
+    -0.0009057378,
+    0.00024199394,
+    -2.6282498e-05,
+];
+
+/// `halfband_taps(95, 12.26)`, which attenuates everything above 0.2915 times the oversampled
+/// sample rate by at least 118 dB.
+const ULTRA_TAPS: [f32; 48] = [
+    -5.57238e-07,
+    4.4351077e-06,
+    -1.585687e-05,
+    4.2710028e-05,
+    -9.783461e-05,
+    0.00020075512,
+    -0.00037956765,
+    0.0006728869,
+    -0.0011317687,
+    0.0018215735,
+    -0.0028238536,
+    0.0042385515,
+    -0.0061871554,
+    0.008818076,
+    -0.012316633,
+    0.016924238,
+    -0.022976082,
+    0.030977689,
+    -0.041769844,
+    0.05691862,
+    -0.07977881,
+    0.1191128,
+    -0.2071831,
+    0.6349287,
+    0.6349287,
+    -0.2071831,
+    0.1191128,
+    -0.07977881,
+    0.05691862,
+    -0.041769844,
+    0.030977689,
+    -0.022976082,
+    0.016924238,
+    -0.012316633,
+    0.008818076,
+    -0.0061871554,
+    0.0042385515,
+    -0.0028238536,
+    0.0018215735,
+    -0.0011317687,
+    0.0006728869,
+    -0.00037956765,
+    0.00020075512,
+    -9.783461e-05,
+    4.2710028e-05,
+    -1.585687e-05,
+    4.4351077e-06,
+    -5.57238e-07,
+];
+
+/// The coefficients for the first-order allpass sections used in minimum-phase mode, in the
+/// polyphase IIR form described by Laurent de Soras in HIIR. The coefficients alternate between
+/// the two allpass chains. These and the coefficients below were computed with HIIR's
+/// `PolyphaseIir2Designer::compute_coefs_spec_order_tbw()`, using:
+///
+/// ```python
+/// compute_coefs_spec_order_tbw(nbr_coefs=2, transition=0.2)
+/// ```
+///
+/// The transition bandwidths are chosen so the stopbands start at the same frequencies as the
+/// linear-phase kernels'. This filter attenuates everything above 0.35 times the oversampled sample
+/// rate by at least 53 dB.
+const DRAFT_ALLPASS_COEFFICIENTS: [f32; 2] = [0.1576056, 0.61484045];
+
+/// `compute_coefs_spec_order_tbw(nbr_coefs=3, transition=0.164)`, which attenuates everything
+/// above 0.332 times the oversampled sample rate by at least 68 dB.
+const NORMAL_ALLPASS_COEFFICIENTS: [f32; 3] = [0.09311579, 0.34598255, 0.73316824];
+
+/// `compute_coefs_spec_order_tbw(nbr_coefs=5, transition=0.124)`, which attenuates everything
+/// above 0.312 times the oversampled sample rate by at least 95 dB.
+const HIGH_ALLPASS_COEFFICIENTS: [f32; 5] =
+    [0.046830438, 0.17602009, 0.36234036, 0.5852333, 0.8457372];
+
+/// `compute_coefs_spec_order_tbw(nbr_coefs=8, transition=0.083)`, which attenuates everything
+/// above 0.2915 times the oversampled sample rate by at least 127 dB.
+const ULTRA_ALLPASS_COEFFICIENTS: [f32; 8] = [
+    0.026182076,
+    0.10018238,
+    0.21025394,
+    0.34196228,
+    0.482878,
+    0.6256442,
+    0.76918703,
+    0.9190243,
 ];
 
-/// The latency introduced by the two filter kernels defined above, in samples.
-const LANZCOS3_KERNEL_LATENCY: usize = LANCZOS3_UPSAMPLING_KERNEL.len() / 2;
+/// The stages from this one onwards use the cheaper relaxed filters below. Starting at the 16x
+/// stage keeps the filters, and thus the output and latency, for 8x oversampling and below the
+/// same.
+const FIRST_RELAXED_STAGE: usize = 3;
+
+/// Only the first stage needs a proper halfband filter. After that the original signal only takes
+/// up a small part of the stage's spectrum, and only the aliases that would fold back into that
+/// part need to be filtered out. At 8x oversampling and up that leaves everything below 0.0625
+/// times the stage's sample rate as the passband, and everything above 0.4375 times it as the
+/// stopband. These kernels use the same Kaiser-windowed sinc design as `NORMAL_TAPS` with the
+/// stopbands reaching the same attenuation over that much wider transition band, so they can be a
+/// lot shorter. This is `halfband_taps(7, 2.4)`, which is also used in place of the lanczos
+/// kernel.
+const NORMAL_RELAXED_TAPS: [f32; 4] = [-0.06888679, 0.5688868, 0.5688868, -0.06888679];
+
+/// `halfband_taps(19, 8.3)`.
+const HIGH_RELAXED_TAPS: [f32; 10] = [
+    0.00012492103,
+    -0.0053132577,
+    0.034601316,
+    -0.13610166,
+    0.6066887,
+    0.6066887,
+    -0.13610166,
+    0.034601316,
+    -0.0053132577,
+    0.00012492103,
+];
+
+/// `halfband_taps(23, 12.7)`.
+const ULTRA_RELAXED_TAPS: [f32; 12] = [
+    -1.561281e-06,
+    0.0004256694,
+    -0.005696861,
+    0.033720635,
+    -0.13373612,
+    0.6052882,
+    0.6052882,
+    -0.13373612,
+    0.033720635,
+    -0.005696861,
+    0.0004256694,
+    -1.561281e-06,
+];
+
+/// The relaxed minimum-phase filters, computed with
+/// `compute_coefs_spec_order_tbw(nbr_coefs=1, transition=0.375)`.
+const DRAFT_RELAXED_ALLPASS_COEFFICIENTS: [f32; 1] = [0.34665644];
+
+/// `compute_coefs_spec_order_tbw(nbr_coefs=2, transition=0.375)`.
+const NORMAL_RELAXED_ALLPASS_COEFFICIENTS: [f32; 2] = [0.11248467, 0.5408055];
+
+/// `compute_coefs_spec_order_tbw(nbr_coefs=3, transition=0.375)`, which already attenuates the
+/// stopband by 134 dB so it's also used for the ultra quality.
+const HIGH_RELAXED_ALLPASS_COEFFICIENTS: [f32; 3] = [0.055907354, 0.24352963, 0.64715314];
+
+/// The halfband filters used by every oversampling stage. Longer filters have steeper transition
+/// bands and attenuate the aliases further, at the cost of more latency and CPU usage. The
+/// documentation for each quality describes its linear-phase kernel.
+#[derive(Enum, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterQuality {
+    /// The original 11-tap lanczos kernel.
+    #[id = "draft"]
+    #[default]
+    Draft,
+    /// A 23-tap kernel.
+    #[id = "normal"]
+    Normal,
+    /// A 47-tap kernel.
+    #[id = "high"]
+    High,
+    /// A 95-tap kernel.
+    #[id = "ultra"]
+    Ultra,
+}
+
+/// Whether the oversampling stages use linear-phase FIR filters or minimum-phase IIR filters.
+#[derive(Enum, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterPhase {
+    /// The halfband FIR kernels. These don't affect the phase, but they add latency.
+    #[id = "linear"]
+    #[default]
+    Linear,
+    /// Polyphase allpass IIR filters with the same stopbands as the linear-phase kernels. These
+    /// only add a couple samples of latency, but they shift the phase of the higher frequencies.
+    #[id = "minimum"]
+    Minimum,
+}
 
 /// A barebones multi-stage linear-phase oversampler that uses the lanzcos kernel with a=3 for a
 /// good approximation of a windowed sinc with only a 11 point kernel function (the kernel is
-/// actually 13 points, but the outer two points are both zero can can thus be omitted). This can be
-/// done much more efficiently but I was in a hurry and this is simple to implement without having
-/// to look anything up.
+/// actually 13 points, but the outer two points are both zero can can thus be omitted). The stages
+/// filter in polyphase form, so the kernel's zero taps are skipped entirely. Longer halfband
+/// kernels with steeper transition bands, or minimum-phase IIR filters, can be selected using
+/// [`set_filters()`][Self::set_filters()].
 ///
 /// This only handles a single audio channel. Use multiple instances for multichannel audio.
 #[derive(Debug)]
@@ -76,64 +301,139 @@ pub struct Lanczos3Oversampler {
     /// the number of stages can change without allocating. The number of currently active
     /// stages/the oversampling factor passed to [`process()`][Self::process()] determines how many
     /// of these are actually used.
-    stages: Vec<Lanzcos3Stage>,
+    stages: Vec<HalfbandStage>,
 
-    /// The oversampler's latency. Precomputed for each possible number of active stages.
+    /// The oversampler's latency. Precomputed for each possible number of active stages, and
+    /// recomputed when the filters change.
     latencies: Vec<u32>,
+
+    /// The filters currently used by all stages.
+    quality: FilterQuality,
+    phase: FilterPhase,
 }
 
-/// A single oversampling stage. Contains the ring buffers and current position in that ringbuffer
-/// used for convolving the filter with the inputs in the upsampling and downsampling parts of the
-/// stage.
+/// A single oversampling stage. Contains the history buffers used for filtering the inputs in the
+/// upsampling and downsampling parts of the stage.
+///
+/// Every other tap in a halfband kernel is zero, apart from the center tap. So instead of
+/// zero-stuffing the input and convolving it with the entire kernel, the filters are split into
+/// their polyphase components. Upsampling computes every even output sample from the nonzero taps
+/// and copies the odd output samples straight from the (delayed) input. Downsampling only needs the
+/// nonzero taps for the even input samples, and the center tap for the odd input samples.
+///
+/// In minimum-phase mode the FIR filters are replaced by `AllpassHalfband`s, and the histories
+/// aren't used.
 #[derive(Debug, Clone)]
-struct Lanzcos3Stage {
+struct HalfbandStage {
     /// The amount of oversampling that happens at this stage. Will be 2 for the first stage, 4 for
     /// the second stage, 8 for the third stage, and so forth. Used to calculate the stage's effect
     /// on the oversampling's latency.
     oversampling_amount: usize,
-
-    /// These ring buffers contain `LANCZOS3_UPSAMPLING_KERNEL.len()` samples. The upsampling ring
-    /// buffer contains room to delay the signal further to make sure the _total_
-    /// (upsampling+downsampling) latency imposed on the signal is divisible by the stage's
-    /// oversampling amount. That is needed to avoid fractional latency.
-    upsampling_rb: Vec<f32>,
-    upsampling_write_pos: usize,
+    /// Whether this stage uses the cheaper relaxed filters. See `FIRST_RELAXED_STAGE`.
+    relaxed: bool,
+
+    /// The nonzero taps of this stage's halfband kernel, apart from the center tap. See
+    /// `LANCZOS3_TAPS`.
+    taps: &'static [f32],
+    phase: FilterPhase,
+
+    /// The upsampling filter's input history, at this stage's input sample rate. This contains
+    /// room to delay the signal further to make sure the _total_ (upsampling+downsampling) latency
+    /// imposed on the signal is divisible by the stage's oversampling amount. That is needed to
+    /// avoid fractional latency.
+    upsampling_history: History,
     /// The additional delay for the upsampling needed to make this stage impose an integer amount
-    /// of latency. The stage's _total_ (upsampling+downsampling) latency needs to be divisible by
-    /// the stage's oversampling amount.
+    /// of latency, at this stage's output sample rate. The stage's _total_
+    /// (upsampling+downsampling) latency needs to be divisible by the stage's oversampling amount.
     additional_upsampling_latency: usize,
 
     /// No additional latency needs to be imposed for the downsampling, so to keep things simple
-    /// this doesn't add any additional delay.
-    downsampling_rb: [f32; LANCZOS3_DOWNSAMPLING_KERNEL.len()],
-    downsampling_write_pos: usize,
+    /// this doesn't add any additional delay. The even and odd input samples are kept apart since
+    /// they're filtered differently.
+    downsampling_even_history: History,
+    downsampling_odd_history: History,
+
+    /// The filters used instead of the FIR filters in minimum-phase mode.
+    upsampling_allpasses: AllpassHalfband,
+    downsampling_allpasses: AllpassHalfband,
 
     scratch_buffer: Vec<f32>,
 }
 
+/// A polyphase IIR halfband filter, made up of two parallel chains of first-order allpass sections.
+/// Like the FIR filters, this runs at the stage's input sample rate when upsampling and its output
+/// sample rate when downsampling, with the chains handling the even and odd samples.
+#[derive(Debug, Clone)]
+struct AllpassHalfband {
+    /// The sections' coefficients, alternating between the first and the second chain.
+    coefficients: &'static [f32],
+    /// The state for each section, in the same order as the coefficients. This is allocated for
+    /// the longest set of coefficients.
+    sections: Vec<AllpassSection>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct AllpassSection {
+    x1: f32,
+    y1: f32,
+}
+
+/// A ring buffer where every sample is written twice, one buffer length apart. That way the most
+/// recent samples can always be read as a single contiguous slice, which can be convolved without
+/// having to deal with wrapping around.
+#[derive(Debug, Clone)]
+struct History {
+    /// Twice the history's maximum length, so the length can be changed without allocating.
+    buffer: Vec<f32>,
+    len: usize,
+    /// The position of the oldest sample, and the next sample's write position.
+    pos: usize,
+}
+
 impl Lanczos3Oversampler {
     /// Create a new oversampler that can oversample to up to the specified oversampling factor, or
     /// the 2-logarithm of the oversampling amount. 1x oversampling (aka, do nothing) = 0, 2x
     /// oversampling = 1, 4x oversampling = 3, etc. The actual amount of oversampling stages used is
     /// passed to the `process()` function, and must be set to `max_factor` or lower.
     pub fn new(maximum_block_size: usize, max_factor: usize) -> Self {
+        let quality = FilterQuality::default();
+        let phase = FilterPhase::default();
+
         let mut stages = Vec::with_capacity(max_factor);
         for stage in 0..max_factor {
-            stages.push(Lanzcos3Stage::new(maximum_block_size, stage))
+            stages.push(HalfbandStage::new(
+                maximum_block_size,
+                stage,
+                quality,
+                phase,
+            ))
         }
 
-        // Since the number of active oversampling stages is passed to the process function, we also
-        // need to know the effective latencies of all possible oversampling settings in advance.
-        let latencies = stages
-            .iter()
-            .map(|stage| stage.effective_latency())
-            .scan(0, |total_latency, latency| {
-                *total_latency += latency;
-                Some(*total_latency)
-            })
-            .collect();
-
-        Self { stages, latencies }
+        let mut oversampler = Self {
+            stages,
+            latencies: vec![0; max_factor],
+            quality,
+            phase,
+        };
+        oversampler.update_latencies();
+
+        oversampler
+    }
+
+    /// Change the filters used by the oversampling stages. The filters are reset when this changes
+    /// them, and the [`latency()`][Self::latency()] changes with them.
+    pub fn set_filters(&mut self, quality: FilterQuality, phase: FilterPhase) {
+        if quality == self.quality && phase == self.phase {
+            return;
+        }
+
+        self.quality = quality;
+        self.phase = phase;
+        for stage in &mut self.stages {
+            stage.set_filters(quality, phase);
+        }
+
+        self.update_latencies();
     }
 
     /// Reset the oversampling filters to their initial states.
@@ -143,8 +443,26 @@ impl Lanczos3Oversampler {
         }
     }
 
+    /// The highest latency [`latency()`][Self::latency()] can return for the given oversampling
+    /// factor with any of the filters. This allocates, so it should only be used to allocate
+    /// buffers up front.
+    pub fn max_latency(factor: usize) -> u32 {
+        let mut oversampler = Self::new(0, factor);
+
+        let mut max_latency = 0;
+        for quality in (0..FilterQuality::variants().len()).map(FilterQuality::from_index) {
+            for phase in (0..FilterPhase::variants().len()).map(FilterPhase::from_index) {
+                oversampler.set_filters(quality, phase);
+                max_latency = max_latency.max(oversampler.latency(factor));
+            }
+        }
+
+        max_latency
+    }
+
     /// Get the latency in samples for the given oversampling factor. Fractional latency is
-    /// automatically avoided.
+    /// automatically avoided. In minimum-phase mode this is the filters' group delay at DC,
+    /// rounded to whole samples.
     ///
     /// # Panics
     ///
@@ -207,8 +525,52 @@ impl Lanczos3Oversampler {
         self.upsample_from(block, factor)
     }
 
+    /// A downsample-only version of `process`, to be used after
+    /// [`upsample_only()`][Self::upsample_only()]. `f` is passed the same upsampled buffer that was
+    /// returned from that function, so it can write the processed signal back to it before it gets
+    /// downsampled to `block`. Useful for processing the upsampled signals of multiple channels
+    /// together.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `factor > max_factor`, or if `block`'s length is longer than the maximum block
+    /// size.
+    pub fn downsample_only(
+        &mut self,
+        block: &mut [f32],
+        factor: usize,
+        f: impl FnOnce(&mut [f32]),
+    ) {
+        assert!(factor <= self.stages.len());
+
+        // This is the 1x oversampling case, `upsample_only()` returned `block` itself
+        if factor == 0 {
+            f(block);
+            return;
+        }
+
+        assert!(
+            block.len() <= self.stages[0].scratch_buffer.len() / 2,
+            "The block's size exceeds the maximum block size"
+        );
+
+        let upsampled_len = block.len() * 2usize.pow(factor as u32);
+        f(&mut self.stages[factor - 1].scratch_buffer[..upsampled_len]);
+        self.downsample_to(block, factor)
+    }
+
+    /// Since the number of active oversampling stages is passed to the process function, we also
+    /// need to know the effective latencies of all possible oversampling settings in advance.
+    fn update_latencies(&mut self) {
+        let mut total_latency = 0.0;
+        for (stage, latency) in self.stages.iter().zip(self.latencies.iter_mut()) {
+            total_latency += stage.effective_latency();
+            *latency = total_latency.round() as u32;
+        }
+    }
+
     /// Upsample `block` through `factor` oversampling stages. Returns a reference to the
-    /// oversampled output stored in the last `LancZos3Stage`'s scratch buffer **with the correct
+    /// oversampled output stored in the last `HalfbandStage`'s scratch buffer **with the correct
     /// length**. This is a multiple of `block`'s length, which may be shorter than the entire
     /// scratch buffer's length if `block` is shorter than the configured maximum block length.
     ///
@@ -274,22 +636,96 @@ impl Lanczos3Oversampler {
     }
 }
 
-impl Lanzcos3Stage {
+impl FilterQuality {
+    /// The nonzero taps of this quality's halfband kernel, apart from the center tap. `relaxed`
+    /// selects the cheaper kernels for the later stages, see `FIRST_RELAXED_STAGE`.
+    fn taps(&self, relaxed: bool) -> &'static [f32] {
+        match (self, relaxed) {
+            (FilterQuality::Draft, false) => &LANCZOS3_TAPS,
+            (FilterQuality::Normal, false) => &NORMAL_TAPS,
+            (FilterQuality::High, false) => &HIGH_TAPS,
+            (FilterQuality::Ultra, false) => &ULTRA_TAPS,
+            (FilterQuality::Draft | FilterQuality::Normal, true) => &NORMAL_RELAXED_TAPS,
+            (FilterQuality::High, true) => &HIGH_RELAXED_TAPS,
+            (FilterQuality::Ultra, true) => &ULTRA_RELAXED_TAPS,
+        }
+    }
+
+    /// The allpass coefficients used for this quality in minimum-phase mode.
+    fn allpass_coefficients(&self, relaxed: bool) -> &'static [f32] {
+        match (self, relaxed) {
+            (FilterQuality::Draft, false) => &DRAFT_ALLPASS_COEFFICIENTS,
+            (FilterQuality::Normal, false) => &NORMAL_ALLPASS_COEFFICIENTS,
+            (FilterQuality::High, false) => &HIGH_ALLPASS_COEFFICIENTS,
+            (FilterQuality::Ultra, false) => &ULTRA_ALLPASS_COEFFICIENTS,
+            (FilterQuality::Draft, true) => &DRAFT_RELAXED_ALLPASS_COEFFICIENTS,
+            (FilterQuality::Normal, true) => &NORMAL_RELAXED_ALLPASS_COEFFICIENTS,
+            (FilterQuality::High | FilterQuality::Ultra, true) => {
+                &HIGH_RELAXED_ALLPASS_COEFFICIENTS
+            }
+        }
+    }
+}
+
+impl HalfbandStage {
     /// Create a `stage_number`th oversampling stage, where `stage_number` is this stage's
     /// zero-based index in a list of stages. Stage 0 handles the 2x oversampling, stage 1 handles
     /// the 4x oversampling, stage 2 handles the 8x oversampling, etc.. This is used to make sure
-    /// the stage's effect on the total latency is always an integer amount.
+    /// the stage's effect on the total latency is always an integer amount, and to pick the cheaper
+    /// relaxed filters for the later stages.
     ///
     /// The maximum block size is used to allocate enough scratch space for oversampling that many
     /// samples *at the base sample rate*. The scratch buffer's size automatically takes the stage
-    /// number into account.
-    pub fn new(maximum_block_size: usize, stage_number: usize) -> Self {
+    /// number into account. The filters' states are allocated for the longest kernel so the stage
+    /// can switch filters later without allocating.
+    pub fn new(
+        maximum_block_size: usize,
+        stage_number: usize,
+        quality: FilterQuality,
+        phase: FilterPhase,
+    ) -> Self {
         let oversampling_amount = 2usize.pow(stage_number as u32 + 1);
+        let relaxed = stage_number >= FIRST_RELAXED_STAGE;
+        let max_taps = ULTRA_TAPS.len();
+        let max_allpass_coefficients = ULTRA_ALLPASS_COEFFICIENTS.len();
 
-        // In theory we would only need to delay one of these, but we'll distribute the delay
-        // cleanly
-        assert!(LANCZOS3_UPSAMPLING_KERNEL.len() == LANCZOS3_DOWNSAMPLING_KERNEL.len());
-        assert!(LANCZOS3_UPSAMPLING_KERNEL.len() % 2 == 1);
+        let mut stage = Self {
+            oversampling_amount,
+            relaxed,
+            taps: quality.taps(relaxed),
+            phase,
+
+            // The additional delay is always smaller than the oversampling amount
+            upsampling_history: History::new(max_taps + oversampling_amount / 2),
+            additional_upsampling_latency: 0,
+
+            downsampling_even_history: History::new(max_taps),
+            downsampling_odd_history: History::new(max_taps / 2),
+
+            upsampling_allpasses: AllpassHalfband::new(max_allpass_coefficients),
+            downsampling_allpasses: AllpassHalfband::new(max_allpass_coefficients),
+
+            scratch_buffer: vec![0.0; maximum_block_size * oversampling_amount],
+        };
+        stage.set_filters(quality, phase);
+
+        stage
+    }
+
+    /// Switch to different filters. This also resets the stage.
+    pub fn set_filters(&mut self, quality: FilterQuality, phase: FilterPhase) {
+        self.phase = phase;
+        self.upsampling_allpasses
+            .set_coefficients(quality.allpass_coefficients(self.relaxed));
+        self.downsampling_allpasses
+            .set_coefficients(quality.allpass_coefficients(self.relaxed));
+
+        // The full kernel has `2 * taps.len() - 1` taps, which needs to be odd with the center tap
+        // at an odd index so every other tap on both sides of it is zero
+        let taps = quality.taps(self.relaxed);
+        assert!(taps.len() % 2 == 0);
+        assert!(taps.len() <= ULTRA_TAPS.len());
+        self.taps = taps;
 
         // This is the latency of the upsampling and downsampling filter, at the base sample rate.
         // Because this stage's filtering happens at a higher sample rate (`oversampling_amount`
@@ -297,48 +733,62 @@ impl Lanzcos3Stage {
         // sample rate_ results in an integer amount of latency at the base sample rate. To do that,
         // the delay needs to be divisible by `oversampling_amount`. This extra delay is only
         // applied to the upsampling part to keep the downsampling simpler.
-        let uncompensated_stage_latency = LANZCOS3_KERNEL_LATENCY + LANZCOS3_KERNEL_LATENCY;
+        let uncompensated_stage_latency = self.kernel_latency() + self.kernel_latency();
 
         // Say the oversampling amount is 4, then an uncompensated stage latency of 8 results in 0
         // additional samples of delay, 9 in 3, 10 in 2, 11 in 1, 12 in 0, etc. This is added to the
         // upsampling filter.
         let additional_delay_required = (-(uncompensated_stage_latency as isize))
-            .rem_euclid(oversampling_amount as isize)
+            .rem_euclid(self.oversampling_amount as isize)
             as usize;
 
-        Self {
-            oversampling_amount,
-
-            upsampling_rb: vec![0.0; LANCZOS3_UPSAMPLING_KERNEL.len() + additional_delay_required],
-            upsampling_write_pos: 0,
-            additional_upsampling_latency: additional_delay_required,
-
-            downsampling_rb: [0.0; LANCZOS3_DOWNSAMPLING_KERNEL.len()],
-            downsampling_write_pos: 0,
-
-            scratch_buffer: vec![0.0; maximum_block_size * oversampling_amount],
-        }
+        // The upsampling history is kept at the input sample rate, so the additional delay needs
+        // to be a whole number of input samples. That's always the case since both the
+        // uncompensated latency and the oversampling amount are even.
+        assert!(additional_delay_required % 2 == 0);
+        self.additional_upsampling_latency = additional_delay_required;
+
+        self.upsampling_history
+            .set_len(taps.len() + additional_delay_required / 2);
+        self.downsampling_even_history.set_len(taps.len());
+        self.downsampling_odd_history
+            .set_len(self.kernel_latency() / 2 + 1);
     }
 
     pub fn reset(&mut self) {
-        // Resetting the positions is not needed, but it also doesn't hurt
-        self.upsampling_rb.fill(0.0);
-        self.upsampling_write_pos = 0;
+        self.upsampling_history.reset();
+        self.downsampling_even_history.reset();
+        self.downsampling_odd_history.reset();
+        self.upsampling_allpasses.reset();
+        self.downsampling_allpasses.reset();
+    }
 
-        self.downsampling_rb.fill(0.0);
-        self.downsampling_write_pos = 0;
+    /// The latency introduced by this stage's upsampling or downsampling filter, in samples at the
+    /// stage's output sample rate.
+    fn kernel_latency(&self) -> usize {
+        self.taps.len() - 1
     }
 
     /// The stage's effect on the oversampling's latency as a whole. This is already divided by the
-    /// stage's oversampling amount.
-    pub fn effective_latency(&self) -> u32 {
-        let uncompensated_stage_latency = LANZCOS3_KERNEL_LATENCY + LANZCOS3_KERNEL_LATENCY;
+    /// stage's oversampling amount. This is always an integer in linear-phase mode.
+    pub fn effective_latency(&self) -> f32 {
+        if self.phase == FilterPhase::Minimum {
+            // The downsampling outputs the odd samples instead of the even ones, see
+            // `downsample_to()`, which makes it one sample early
+            let stage_latency = self.upsampling_allpasses.dc_group_delay()
+                + self.downsampling_allpasses.dc_group_delay()
+                - 1.0;
+
+            return stage_latency / self.oversampling_amount as f32;
+        }
+
+        let uncompensated_stage_latency = self.kernel_latency() + self.kernel_latency();
         let total_stage_latency = uncompensated_stage_latency + self.additional_upsampling_latency;
 
         let effective_latency = total_stage_latency as f32 / self.oversampling_amount as f32;
         assert!(effective_latency.fract() == 0.0);
 
-        effective_latency as u32
+        effective_latency
     }
 
     /// Upsample `block` 2x and write the results to this stage's scratch buffer.
@@ -350,84 +800,44 @@ impl Lanzcos3Stage {
         let output_length = block.len() * 2;
         assert!(output_length <= self.scratch_buffer.len());
 
-        // We'll first zero-stuff the input, and then run that through the lanczos halfband filter
-        for (input_sample_idx, input_sample) in block.iter().enumerate() {
-            let output_sample_idx = input_sample_idx * 2;
-            self.scratch_buffer[output_sample_idx] = *input_sample;
-            self.scratch_buffer[output_sample_idx + 1] = 0.0;
-        }
-
-        // The zero-stuffed input is now run through the lanczos filter, which is a windowed sinc
-        // filter where every even tap has a value of zero. That means that if the filter is
-        // centered on a non-zero sample, the output must be equal to that sample and we can thus
-        // skip the convolution step entirely. Another important consideration is that we are
-        // imposing an additional `self.additional_upsampling_latency` samples of delay on the input
-        // to make sure the effective latency of the oversampling is always an integer amount.
-        let mut direct_read_pos =
-            (self.upsampling_write_pos + LANZCOS3_KERNEL_LATENCY) % self.upsampling_rb.len();
-        for output_sample_idx in 0..output_length {
-            // For a more intuitive description, imagine that `self.additional_upsampling_latency`
-            // is 2, and `self.upsampling_write_pos` is currently 0. For an 11-tap filter (like the
-            // lanczos3 kernel with the zero points removed from both ends), the situation after
-            // this statement would look like this:
-            //
-            // [n, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]
-            //  ^-- self.upsampling_write_pos
-            self.upsampling_rb[self.upsampling_write_pos] = self.scratch_buffer[output_sample_idx];
-
-            // The read/write head position needs to be incremented before filtering so that the
-            // just-added sample becomes the last sample in the ring buffer (if the additional
-            // latency/delay is 0)
-            self.upsampling_write_pos += 1;
-            if self.upsampling_write_pos == self.upsampling_rb.len() {
-                self.upsampling_write_pos = 0;
+        if self.phase == FilterPhase::Minimum {
+            // Zero-stuffing means the first chain only ever sees the even samples and the second
+            // chain only the odd samples, so both chains can run on the input directly. The 2x gain
+            // that compensates for the zero-stuffing cancels out the halfband filter's 0.5 gain.
+            for (input_sample, output_samples) in block
+                .iter()
+                .zip(self.scratch_buffer[..output_length].chunks_exact_mut(2))
+            {
+                let (even, odd) = self
+                    .upsampling_allpasses
+                    .process(*input_sample, *input_sample);
+
+                output_samples[0] = even;
+                output_samples[1] = odd;
             }
 
-            direct_read_pos += 1;
-            if direct_read_pos == self.upsampling_rb.len() {
-                direct_read_pos = 0;
-            }
+            return;
+        }
 
-            // We can now read starting from the new `self.upsampling_write_pos`. This will cause
-            // the output to be delayed by `self.additional_upsampling_latency` samples. The range
-            // used for convolution is visualized below. It in this example it takes 2 additional
-            // iterations of this loop before sample `n` is considered again. Even output samples
-            // can directly be read from the ring buffer without convolution at the visualized
-            // offset.
-            //
-            // [n, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]
-            //     ^--------------^---------------^
-            //                    └- direct_read_position
-            //
-            // NOTE: 'Even samples' is considered from the perspective of a zero latency filter. In
-            //       this case the evenness of the filter's latency also needs to be considered. If
-            //       it's odd then the direct reading should also happen for odd indexed samples.
-            self.scratch_buffer[output_sample_idx] =
-                if output_sample_idx % 2 == (LANZCOS3_KERNEL_LATENCY % 2) {
-                    nih_debug_assert_eq!(
-                        self.upsampling_rb[(direct_read_pos + self.upsampling_rb.len() - 1)
-                            % self.upsampling_rb.len()],
-                        0.0
-                    );
-                    nih_debug_assert_eq!(
-                        self.upsampling_rb[(direct_read_pos + 1) % self.upsampling_rb.len()],
-                        0.0
-                    );
-
-                    self.upsampling_rb[direct_read_pos]
-                } else {
-                    convolve_rb(
-                        &self.upsampling_rb,
-                        &LANCZOS3_UPSAMPLING_KERNEL,
-                        self.upsampling_write_pos,
-                    )
-                };
+        for (input_sample, output_samples) in block
+            .iter()
+            .zip(self.scratch_buffer[..output_length].chunks_exact_mut(2))
+        {
+            self.upsampling_history.push(*input_sample);
+
+            // The oldest samples in the history are the ones that have been delayed by the
+            // additional latency. Convolving the nonzero taps with those results in the even output
+            // sample. The odd output sample lines up with the kernel's center tap, which is 1, and
+            // every other tap that would be applied to it is either zero or lines up with a
+            // zero-stuffed sample. So that sample is the delayed input in the middle of the taps.
+            let history = &self.upsampling_history.samples()[..self.taps.len()];
+            output_samples[0] = convolve(history, self.taps);
+            output_samples[1] = history[self.taps.len() / 2];
         }
     }
 
-    /// Downsample starting from the last oversampling stage, writing the results from downsampling
-    /// the first stage to `block`. `block`'s actual length is taken into account to compute the
-    /// length of the oversampled blocks.
+    /// Downsample this stage's scratch buffer 2x and write the results to `block`. `block`'s
+    /// length is used to determine how many samples should be read from the scratch buffer.
     ///
     /// # Panics
     ///
@@ -436,71 +846,175 @@ impl Lanzcos3Stage {
         let input_length = block.len() * 2;
         assert!(input_length <= self.scratch_buffer.len());
 
-        // The additional delay to make the latency integer has already been taken into account in
-        // the upsampling part, so the downsampling is more straightforward
-        for input_sample_idx in 0..input_length {
-            self.downsampling_rb[self.downsampling_write_pos] =
-                self.scratch_buffer[input_sample_idx];
-
-            // The read/write head position needs to be incremented before filtering so that the
-            // just-added sample becomes the last sample in the ring buffer
-            self.downsampling_write_pos += 1;
-            if self.downsampling_write_pos == LANCZOS3_DOWNSAMPLING_KERNEL.len() {
-                self.downsampling_write_pos = 0;
+        if self.phase == FilterPhase::Minimum {
+            // The second chain normally filters the odd samples delayed by one sample. Keeping the
+            // filtered odd samples instead of the even ones avoids that delay, so the odd input
+            // samples go through the first chain and the even samples through the second chain.
+            for (output_sample, input_samples) in block
+                .iter_mut()
+                .zip(self.scratch_buffer[..input_length].chunks_exact(2))
+            {
+                let (odd, even) = self
+                    .downsampling_allpasses
+                    .process(input_samples[1], input_samples[0]);
+
+                *output_sample = 0.5 * (odd + even);
             }
 
-            // Because downsampling by a factor of two is filtering followed by decimation (where
-            // you take every even sample), we only need to compute the filtered output for the even
-            // samples. This is similar to how we only need to filter half the samples in the
-            // upsampling step.
-            if input_sample_idx % 2 == 0 {
-                let output_sample_idx = input_sample_idx / 2;
-                block[output_sample_idx] = convolve_rb(
-                    &self.downsampling_rb,
-                    // NOTE: This is `LANCZOS3_UPSAMPLING_KERNEL`, but with a factor two gain
-                    //       decrease to compensate for the 2x gain increase that happened during
-                    //       the upsampling
-                    &LANCZOS3_DOWNSAMPLING_KERNEL,
-                    self.downsampling_write_pos,
-                )
-            }
+            return;
+        }
+
+        // The additional delay to make the latency integer has already been taken into account in
+        // the upsampling part, so the downsampling is more straightforward. Downsampling by a
+        // factor of two is filtering followed by decimation, so only every other filtered sample
+        // needs to be computed. Those line up the nonzero taps with the even input samples, and
+        // the center tap with an odd input sample from `self.kernel_latency()` samples ago.
+        for (output_sample, input_samples) in block
+            .iter_mut()
+            .zip(self.scratch_buffer[..input_length].chunks_exact(2))
+        {
+            self.downsampling_even_history.push(input_samples[0]);
+
+            // NOTE: These are the upsampling filter's taps, but with a factor two gain decrease to
+            //       compensate for the 2x gain increase that happened during the upsampling
+            *output_sample = 0.5
+                * (convolve(self.downsampling_even_history.samples(), self.taps)
+                    + self.downsampling_odd_history.samples()[0]);
+
+            self.downsampling_odd_history.push(input_samples[1]);
         }
     }
 }
 
-/// Convolve `input_ring_buffer` with `kernel`, with `input_ring_buffer` rotated so that it starts
-/// at `ring_buffer_pos` and then wraps back around to the start.
-///
-/// # Panics
-///
-/// Assumes `input_ring_buffer` and `kernel` have the same length. May panic if they don't.
-fn convolve_rb(input_ring_buffer: &[f32], kernel: &[f32], ring_buffer_pos: usize) -> f32 {
-    let mut total = 0.0;
+impl AllpassHalfband {
+    fn new(max_coefficients: usize) -> Self {
+        Self {
+            coefficients: &[],
+            sections: vec![AllpassSection::default(); max_coefficients],
+        }
+    }
 
-    nih_debug_assert!(input_ring_buffer.len() >= kernel.len());
+    /// Switch to different coefficients. This also resets the filter.
+    fn set_coefficients(&mut self, coefficients: &'static [f32]) {
+        assert!(coefficients.len() <= self.sections.len());
 
-    // This is straightforward convolution. Could be implemented much more efficiently, but for our
-    // 11-tap filter this works fine
-    let num_samples_until_wraparound =
-        (input_ring_buffer.len() - ring_buffer_pos).min(kernel.len());
-    for (read_pos_offset, kernel_sample) in kernel
-        .iter()
-        .rev()
-        .take(num_samples_until_wraparound)
-        .enumerate()
-    {
-        total += kernel_sample * input_ring_buffer[ring_buffer_pos + read_pos_offset];
+        self.coefficients = coefficients;
+        self.reset();
     }
 
-    for (read_pos, kernel_sample) in kernel
-        .iter()
-        .rev()
-        // Needs to happen before the `enumerate`
-        .skip(num_samples_until_wraparound)
-        .enumerate()
-    {
-        total += kernel_sample * input_ring_buffer[read_pos];
+    fn reset(&mut self) {
+        self.sections.fill(AllpassSection::default());
     }
 
-    total
+    /// Filter a sample through each chain, returning both chains' outputs. Each section is the
+    /// first-order allpass `(a + z^-1) / (1 + a * z^-1)` at this filter's input sample rate, which
+    /// is `(a + z^-2) / (1 + a * z^-2)` at the rate the halfband filter runs at.
+    #[inline]
+    fn process(&mut self, first: f32, second: f32) -> (f32, f32) {
+        let mut samples = [first, second];
+        for (i, (coefficient, section)) in self
+            .coefficients
+            .iter()
+            .zip(self.sections.iter_mut())
+            .enumerate()
+        {
+            let x = &mut samples[i % 2];
+            let y = coefficient * (*x - section.y1) + section.x1;
+
+            section.x1 = *x;
+            section.y1 = y;
+            *x = y;
+        }
+
+        (samples[0], samples[1])
+    }
+
+    /// The halfband filter's group delay at DC, in samples at the rate the halfband filter runs
+    /// at. Both chains have unity gain and zero phase at DC, so this is the average of the two
+    /// chains' group delays. The second chain is delayed by an additional sample.
+    fn dc_group_delay(&self) -> f32 {
+        let section_delay = |a: &f32| 2.0 * (1.0 - a) / (1.0 + a);
+        let first: f32 = self.coefficients.iter().step_by(2).map(section_delay).sum();
+        let second: f32 = self
+            .coefficients
+            .iter()
+            .skip(1)
+            .step_by(2)
+            .map(section_delay)
+            .sum();
+
+        (first + second + 1.0) / 2.0
+    }
+}
+
+impl History {
+    fn new(max_len: usize) -> Self {
+        Self {
+            buffer: vec![0.0; max_len * 2],
+            len: max_len,
+            pos: 0,
+        }
+    }
+
+    /// Change the history's length. This also clears the history.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` exceeds the maximum length the history was created with.
+    fn set_len(&mut self, len: usize) {
+        assert!(len * 2 <= self.buffer.len());
+
+        self.len = len;
+        self.reset();
+    }
+
+    fn reset(&mut self) {
+        self.buffer.fill(0.0);
+        self.pos = 0;
+    }
+
+    #[inline]
+    fn push(&mut self, sample: f32) {
+        self.buffer[self.pos] = sample;
+        self.buffer[self.pos + self.len] = sample;
+
+        self.pos += 1;
+        if self.pos == self.len {
+            self.pos = 0;
+        }
+    }
+
+    /// The history's samples, from oldest to newest.
+    #[inline]
+    fn samples(&self) -> &[f32] {
+        &self.buffer[self.pos..self.pos + self.len]
+    }
+}
+
+/// Take every even tap from an 11-tap halfband kernel.
+const fn even_taps(kernel: &[f32; 11]) -> [f32; 6] {
+    let mut taps = [0.0; 6];
+    let mut i = 0;
+    while i < taps.len() {
+        taps[i] = kernel[i * 2];
+        i += 1;
+    }
+
+    taps
+}
+
+/// Convolve `history`, ordered from oldest to newest, with `kernel`.
+///
+/// # Panics
+///
+/// Assumes `history` and `kernel` have the same length. May panic if they don't.
+#[inline]
+fn convolve(history: &[f32], kernel: &[f32]) -> f32 {
+    nih_debug_assert_eq!(history.len(), kernel.len());
+
+    history
+        .iter()
+        .zip(kernel.iter().rev())
+        .map(|(sample, tap)| sample * tap)
+        .sum()
 }